@@ -0,0 +1,217 @@
+//! polling through the Azkaban web api, for instances without a database
+//!
+//! The api has no query over all jobs, so every flow found in the cron dirs
+//! is asked for its recent executions, one request per execution on top.
+
+use crate::azkaban::{AzkabanClient, ExecFlow, ExecNode};
+use crate::bean::{Job, Task};
+use crate::flapping::{History, JobRuns, Run};
+use crate::state::{job_key, Recovery};
+use crate::utli::{from_millis, status_code};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// executions asked for at once
+const PAGE: usize = 20;
+
+/// Executions of the known flows started since `since`, with their jobs. A
+/// flow or execution Azkaban doesn't answer for is skipped with a warning.
+pub async fn executions(
+    api: &AzkabanClient,
+    parse_jobs: &HashMap<String, HashMap<String, HashMap<String, Job>>>,
+    since: DateTime<Utc>,
+) -> Result<Vec<ExecFlow>> {
+    let since = since.timestamp_millis();
+    let mut result = vec![];
+
+    for (project, flows) in parse_jobs {
+        for flow in flows.keys() {
+            let mut start = 0;
+            loop {
+                let page = match api.fetch_flow_executions(project, flow, start, PAGE).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        println!(
+                            "Warning: Can't fetch executions of {}.{}: {}",
+                            project, flow, e
+                        );
+                        break;
+                    }
+                };
+                let recent: Vec<_> = page.iter().filter(|e| e.start_time >= since).collect();
+                for e in &recent {
+                    match api.fetch_exec_flow(&e.exec_id.to_string()).await {
+                        Ok(exec) => result.push(exec),
+                        Err(err) => {
+                            println!("Warning: Can't fetch execution {}: {}", e.exec_id, err)
+                        }
+                    }
+                }
+                if page.len() < PAGE || recent.len() < page.len() {
+                    break;
+                }
+                start += PAGE;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Jobs that ran in the executions, with the execution they ran in.
+fn jobs(execs: &[ExecFlow]) -> impl Iterator<Item = (&ExecFlow, &ExecNode)> {
+    execs
+        .iter()
+        .flat_map(|e| e.nodes.iter().map(move |n| (e, n)))
+        .filter(|(_, n)| n.job_type != "flow" && n.start_time > 0)
+}
+
+/// Latest run of every job in the executions started since `since` that is
+/// neither running nor succeeded, like `core_sql` but without params and logs.
+pub fn failures(execs: &[ExecFlow], since: DateTime<Utc>, instance: Option<&String>) -> Vec<Task> {
+    let since = since.timestamp_millis();
+    let mut latest: HashMap<String, (&ExecFlow, &ExecNode)> = HashMap::new();
+    for (e, n) in jobs(execs).filter(|(e, _)| e.start_time >= since) {
+        let key = job_key(&e.project, &e.flow_id, &n.id);
+        let newer = latest
+            .get(&key)
+            .is_none_or(|(_, l)| (n.start_time, n.attempt) > (l.start_time, l.attempt));
+        if newer {
+            latest.insert(key, (e, n));
+        }
+    }
+
+    let mut latest: Vec<_> = latest.into_values().collect();
+    latest.sort_by(|a, b| {
+        (&a.0.project, &a.0.flow_id, &a.1.id).cmp(&(&b.0.project, &b.0.flow_id, &b.1.id))
+    });
+
+    let mut result = vec![];
+    for (e, n) in latest {
+        let status = status_code(&n.status);
+        if status == 30 || status == 50 {
+            continue;
+        }
+        let exec_id = e.exec_id.to_string();
+        let (start_time, end_time) = (from_millis(n.start_time), from_millis(n.end_time));
+        result.push(Task {
+            exec_id,
+            project_name: e.project.clone(),
+            flow_id: e.flow_id.clone(),
+            job_id: n.id.clone(),
+            attempt: n.attempt,
+            status,
+            owner: String::new(),
+            input_params: String::new(),
+            output_params: String::new(),
+            start_time,
+            end_time,
+            duration: (end_time - start_time).to_std().unwrap_or(Duration::ZERO),
+            desc: String::new(),
+            log_excerpt: String::new(),
+            source_url: None,
+            unowned: None,
+            severity: Default::default(),
+            team: None,
+            instance: instance.cloned(),
+        });
+    }
+    result
+}
+
+/// Latest successful run of every job, like `success_sql`.
+pub fn recoveries(execs: &[ExecFlow]) -> HashMap<String, Recovery> {
+    let mut result: HashMap<String, Recovery> = HashMap::new();
+    for (e, n) in jobs(execs).filter(|(_, n)| n.status == "SUCCEEDED") {
+        let end_time = from_millis(n.end_time);
+        let key = job_key(&e.project, &e.flow_id, &n.id);
+        if result.get(&key).is_none_or(|r| r.end_time < end_time) {
+            let recovery = Recovery {
+                exec_id: e.exec_id.to_string(),
+                end_time,
+                submit_user: e.submit_user.clone(),
+            };
+            result.insert(key, recovery);
+        }
+    }
+    result
+}
+
+/// Final runs of every job ended since `since`, oldest first, like `history_sql`.
+pub fn history(execs: &[ExecFlow], since: DateTime<Utc>) -> History {
+    let mut runs: Vec<(&ExecFlow, &ExecNode)> = jobs(execs)
+        .filter(|(_, n)| matches!(status_code(&n.status), 50 | 60 | 70 | 80 | 120))
+        .filter(|(_, n)| from_millis(n.end_time) > since)
+        .collect();
+    runs.sort_by_key(|(_, n)| n.end_time);
+
+    let mut result: History = HashMap::new();
+    for (e, n) in runs {
+        result
+            .entry(job_key(&e.project, &e.flow_id, &n.id))
+            .or_insert_with(|| JobRuns {
+                project: e.project.clone(),
+                flow: e.flow_id.clone(),
+                job: n.id.clone(),
+                runs: vec![],
+            })
+            .runs
+            .push(Run {
+                exec_id: e.exec_id.to_string(),
+                attempt: n.attempt,
+                status: status_code(&n.status),
+                end_time: from_millis(n.end_time),
+            });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apipoll::{failures, history, recoveries};
+    use crate::azkaban::ExecFlow;
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn test_from_executions() {
+        let now = Utc::now();
+        let at = |hours: i64| (now - Duration::hours(hours)).timestamp_millis();
+        let execs: Vec<ExecFlow> = serde_json::from_value(json!([
+            {"execid": 2, "project": "warehouse", "flowId": "daily", "status": "FAILED",
+             "submitUser": "azkaban", "startTime": at(2), "endTime": at(1), "nodes": [
+                {"id": "load", "status": "FAILED", "startTime": at(2), "endTime": at(1), "attempt": 1},
+                {"id": "report", "status": "CANCELLED", "startTime": -1, "endTime": -1},
+                {"id": "clean", "status": "SUCCEEDED", "startTime": at(2), "endTime": at(2)}
+            ]},
+            {"execid": 1, "project": "warehouse", "flowId": "daily", "status": "SUCCEEDED",
+             "submitUser": "ou_a", "startTime": at(26), "endTime": at(25), "nodes": [
+                {"id": "load", "status": "SUCCEEDED", "startTime": at(26), "endTime": at(25)},
+                {"id": "clean", "status": "SUCCEEDED", "startTime": at(26), "endTime": at(26)}
+            ]}
+        ]))
+        .unwrap();
+
+        let tasks = failures(&execs, now - Duration::hours(24), None);
+        assert_eq!(failures(&execs, now - Duration::hours(1), None).len(), 0);
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].job_id, "load");
+        assert_eq!((tasks[0].exec_id.as_str(), tasks[0].status), ("2", 70));
+        assert_eq!(tasks[0].duration.as_secs(), 3600);
+
+        let recovered = recoveries(&execs);
+        assert_eq!(recovered["warehouse.daily.load"].exec_id, "1");
+        assert_eq!(recovered["warehouse.daily.clean"].exec_id, "2");
+
+        let runs = history(&execs, now - Duration::days(7));
+        let statuses: Vec<i32> = runs["warehouse.daily.load"]
+            .runs
+            .iter()
+            .map(|r| r.status)
+            .collect();
+        assert_eq!(statuses, vec![50, 70]);
+        assert!(!runs.contains_key("warehouse.daily.report"));
+    }
+}
@@ -0,0 +1,529 @@
+//! client for the Azkaban web api, for where the database is out of reach
+
+use crate::bean::InitConfig;
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+/// fetchExecJobLogs is read in chunks of this size
+const LOG_CHUNK: usize = 50_000;
+/// bytes read to tell whether a log goes on past an offset
+const LOG_PROBE: usize = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecNode {
+    pub id: String,
+    pub status: String,
+    #[serde(default)]
+    pub start_time: i64,
+    #[serde(default)]
+    pub end_time: i64,
+    #[serde(default)]
+    pub attempt: u8,
+    #[serde(default, rename = "type")]
+    pub job_type: String,
+}
+
+/// the api's answer as is, not every field is read
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecFlow {
+    #[serde(rename = "execid")]
+    pub exec_id: i64,
+    pub project: String,
+    pub flow_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub submit_user: String,
+    #[serde(default)]
+    pub start_time: i64,
+    #[serde(default)]
+    pub end_time: i64,
+    #[serde(default)]
+    pub nodes: Vec<ExecNode>,
+}
+
+/// the api's answer as is, not every field is read
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Execution {
+    pub exec_id: i64,
+    pub flow_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub submit_user: String,
+    #[serde(default)]
+    pub start_time: i64,
+    #[serde(default)]
+    pub end_time: i64,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub schedule_id: String,
+    #[serde(default)]
+    pub cron_expression: Option<String>,
+    #[serde(default)]
+    pub next_exec_time: String,
+    #[serde(default)]
+    pub submit_user: String,
+}
+
+pub struct AzkabanClient {
+    client: Client,
+    url: String,
+    username: String,
+    password: String,
+    /// logged in lazily, refreshed when Azkaban answers `{"error": "session"}`
+    session: Mutex<Option<String>>,
+}
+
+impl AzkabanClient {
+    pub fn new(url: &str, username: &str, password: &str) -> Self {
+        AzkabanClient {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            session: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &InitConfig) -> Result<Self> {
+        let api = config
+            .azkaban_api
+            .as_ref()
+            .ok_or_else(|| anyhow!("azkaban_api is not configured"))?;
+        let url = api
+            .url
+            .as_deref()
+            .or(config.azkaban_url.as_deref())
+            .ok_or_else(|| anyhow!("Neither azkaban_api.url nor azkaban_url is set"))?;
+
+        Ok(Self::new(url, &api.username, &api.password))
+    }
+
+    pub async fn login(&self) -> Result<String> {
+        let res: Value = self
+            .client
+            .post(format!("{}/", self.url))
+            .form(&[
+                ("action", "login"),
+                ("username", &self.username),
+                ("password", &self.password),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match res["session.id"].as_str() {
+            Some(id) => Ok(id.to_string()),
+            None => Err(anyhow!("Azkaban login failed: {}", res)),
+        }
+    }
+
+    async fn session(&self, refresh: bool) -> Result<String> {
+        let mut session = self.session.lock().await;
+        match session.as_ref() {
+            Some(id) if !refresh => Ok(id.clone()),
+            _ => {
+                let id = self.login().await?;
+                *session = Some(id.clone());
+                Ok(id)
+            }
+        }
+    }
+
+    /// GET an ajax endpoint, logs in again once if the session expired.
+    async fn ajax(&self, path: &str, params: &[(&str, &str)]) -> Result<Value> {
+        let mut refresh = false;
+        loop {
+            let session = self.session(refresh).await?;
+            let text = self
+                .client
+                .get(format!("{}{}", self.url, path))
+                .query(params)
+                .query(&[("session.id", &session)])
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            // an expired session gets the login page on some versions
+            let expired = match serde_json::from_str::<Value>(&text) {
+                Ok(v) if v["error"] == "session" => true,
+                Ok(v) => {
+                    if let Some(error) = v["error"].as_str() {
+                        return Err(anyhow!("Azkaban {} failed: {}", path, error));
+                    }
+                    return Ok(v);
+                }
+                Err(_) => true,
+            };
+
+            if expired && refresh {
+                return Err(anyhow!("Azkaban {}: session refused after login", path));
+            }
+            refresh = true;
+        }
+    }
+
+    pub async fn fetch_exec_flow(&self, exec_id: &str) -> Result<ExecFlow> {
+        let res = self
+            .ajax(
+                "/executor",
+                &[("ajax", "fetchexecflow"), ("execid", exec_id)],
+            )
+            .await?;
+        Ok(serde_json::from_value(res)?)
+    }
+
+    /// `length` bytes of the log of one attempt from `offset`, and how many bytes
+    /// of the log file that was, which the decoded data needn't match.
+    async fn read_log(
+        &self,
+        exec_id: &str,
+        job_id: &str,
+        attempt: &str,
+        offset: usize,
+        length: usize,
+    ) -> Result<(usize, String)> {
+        let (offset, length) = (offset.to_string(), length.to_string());
+        let res = self
+            .ajax(
+                "/executor",
+                &[
+                    ("ajax", "fetchExecJobLogs"),
+                    ("execid", exec_id),
+                    ("jobId", job_id),
+                    ("attempt", attempt),
+                    ("offset", &offset),
+                    ("length", &length),
+                ],
+            )
+            .await?;
+
+        let data = res["data"].as_str().unwrap_or_default().to_string();
+        let read = res["length"].as_u64().map_or(data.len(), |n| n as usize);
+        Ok((read, data))
+    }
+
+    /// The last `max_bytes` of the log of one attempt. The api doesn't tell the
+    /// size of a log, so the end of one longer than the first read is looked
+    /// for with small reads, doubling the step and then halving it.
+    pub async fn fetch_exec_job_logs(
+        &self,
+        exec_id: &str,
+        job_id: &str,
+        attempt: u8,
+        max_bytes: usize,
+    ) -> Result<String> {
+        let attempt = attempt.to_string();
+        let read =
+            |offset: usize, length: usize| self.read_log(exec_id, job_id, &attempt, offset, length);
+
+        let first = LOG_CHUNK.min(max_bytes).max(LOG_PROBE);
+        let (mut end, head) = read(0, first).await?;
+        if end < first {
+            return Ok(head);
+        }
+
+        // the log goes on past `end` but not past `end + step`
+        let mut step = end;
+        while read(end + step, LOG_PROBE).await?.0 > 0 {
+            end += step;
+            step *= 2;
+        }
+        while step > LOG_PROBE {
+            step /= 2;
+            if read(end + step, LOG_PROBE).await?.0 > 0 {
+                end += step;
+            }
+        }
+
+        let mut offset = end.saturating_sub(max_bytes);
+        let mut log = String::new();
+        loop {
+            let (n, data) = read(offset, LOG_CHUNK).await?;
+            if n == 0 || data.is_empty() {
+                break;
+            }
+            offset += n;
+            log.push_str(&data);
+        }
+
+        if log.len() > max_bytes {
+            let mut cut = log.len() - max_bytes;
+            while !log.is_char_boundary(cut) {
+                cut += 1;
+            }
+            log.drain(..cut);
+        }
+        Ok(log)
+    }
+
+    /// Start a new execution of the flow, `disabled` jobs are skipped. Returns the exec id.
+    pub async fn execute_flow(
+        &self,
+        project: &str,
+        flow: &str,
+        disabled: &[String],
+    ) -> Result<String> {
+        let disabled = serde_json::to_string(disabled)?;
+        let mut params = vec![
+            ("ajax", "executeFlow"),
+            ("project", project),
+            ("flow", flow),
+        ];
+        if disabled != "[]" {
+            params.push(("disabled", &disabled));
+        }
+
+        let res = self.ajax("/executor", &params).await?;
+        match &res["execid"] {
+            Value::Number(n) => Ok(n.to_string()),
+            Value::String(s) => Ok(s.clone()),
+            _ => Err(anyhow!("Unexpected executeFlow response: {}", res)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn cancel_flow(&self, exec_id: &str) -> Result<()> {
+        self.ajax("/executor", &[("ajax", "cancelFlow"), ("execid", exec_id)])
+            .await?;
+        Ok(())
+    }
+
+    /// Latest executions of a flow, newest first.
+    pub async fn fetch_flow_executions(
+        &self,
+        project: &str,
+        flow: &str,
+        start: usize,
+        length: usize,
+    ) -> Result<Vec<Execution>> {
+        let (start, length) = (start.to_string(), length.to_string());
+        let res = self
+            .ajax(
+                "/manager",
+                &[
+                    ("ajax", "fetchFlowExecutions"),
+                    ("project", project),
+                    ("flow", flow),
+                    ("start", &start),
+                    ("length", &length),
+                ],
+            )
+            .await?;
+        Ok(serde_json::from_value(res["executions"].clone())?)
+    }
+
+    /// The schedule of a flow, `None` when it is not scheduled.
+    #[allow(dead_code)]
+    pub async fn fetch_schedule(&self, project_id: &str, flow: &str) -> Result<Option<Schedule>> {
+        let res = self
+            .ajax(
+                "/schedule",
+                &[
+                    ("ajax", "fetchSchedule"),
+                    ("projectId", project_id),
+                    ("flowId", flow),
+                ],
+            )
+            .await?;
+        match res.get("schedule") {
+            Some(s) => Ok(Some(serde_json::from_value(s.clone())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::azkaban::AzkabanClient;
+    use hyper::body::to_bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Mock {
+        logins: usize,
+        requests: Vec<HashMap<String, String>>,
+        /// job log served, a short one when empty
+        log: String,
+    }
+
+    fn params(query: &str) -> HashMap<String, String> {
+        query
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| {
+                (
+                    k.to_string(),
+                    v.replace("%5B", "[")
+                        .replace("%5D", "]")
+                        .replace("%22", "\""),
+                )
+            })
+            .collect()
+    }
+
+    fn answer(mock: &mut Mock, path: &str, p: &HashMap<String, String>) -> Value {
+        if p.get("session.id") != Some(&format!("s{}", mock.logins)) {
+            return json!({"error": "session"});
+        }
+        mock.requests.push(p.clone());
+
+        let ajax = p.get("ajax").map(String::as_str).unwrap_or_default();
+        match (path, ajax) {
+            ("/executor", "fetchexecflow") => json!({
+                "execid": 304, "project": "warehouse", "flowId": "daily", "status": "FAILED",
+                "submitUser": "azkaban", "startTime": 1, "endTime": 2,
+                "nodes": [
+                    {"id": "a", "status": "SUCCEEDED", "attempt": 0, "type": "command"},
+                    {"id": "b", "status": "FAILED", "attempt": 1, "type": "command"}
+                ]
+            }),
+            ("/executor", "fetchExecJobLogs") => {
+                let log = match mock.log.as_str() {
+                    "" => "line 1\nline 2\n",
+                    log => log,
+                };
+                let offset: usize = p["offset"].parse().unwrap();
+                let length: usize = p["length"].parse().unwrap();
+                let data = &log[offset.min(log.len())..(offset + length).min(log.len())];
+                json!({"data": data, "offset": offset, "length": data.len()})
+            }
+            ("/executor", "executeFlow") => {
+                json!({"execid": 305, "project": "warehouse", "flow": "daily"})
+            }
+            ("/executor", "cancelFlow") => {
+                json!({"error": "Execution 304 of flow daily isn't running."})
+            }
+            ("/manager", "fetchFlowExecutions") => json!({
+                "executions": [
+                    {"execId": 305, "flowId": "daily", "status": "RUNNING", "submitUser": "azkaban", "startTime": 3},
+                    {"execId": 304, "flowId": "daily", "status": "FAILED", "submitUser": "azkaban", "startTime": 1, "endTime": 2}
+                ],
+                "total": 2
+            }),
+            ("/schedule", "fetchSchedule") if p["flowId"] == "daily" => json!({
+                "schedule": {"scheduleId": "3", "cronExpression": "0 0 2 ? * *", "nextExecTime": "2025-08-01 02:00:00", "submitUser": "azkaban"}
+            }),
+            ("/schedule", "fetchSchedule") => json!({}),
+            _ => json!({"error": format!("unknown {} {}", path, ajax)}),
+        }
+    }
+
+    async fn mock_server() -> (String, Arc<Mutex<Mock>>) {
+        let mock = Arc::new(Mutex::new(Mock::default()));
+        let shared = mock.clone();
+
+        let make = make_service_fn(move |_| {
+            let mock = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let mock = mock.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let query = req.uri().query().unwrap_or_default().to_string();
+                        let body = to_bytes(req.into_body()).await.unwrap_or_default();
+
+                        let mut mock = mock.lock().unwrap();
+                        let res = if path == "/" {
+                            let form = params(&String::from_utf8_lossy(&body));
+                            assert_eq!(form["action"], "login");
+                            mock.logins += 1;
+                            json!({"session.id": format!("s{}", mock.logins), "status": "success"})
+                        } else {
+                            answer(&mut mock, &path, &params(&query))
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(res.to_string())))
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, mock)
+    }
+
+    #[tokio::test]
+    async fn test_client_against_mock() -> anyhow::Result<()> {
+        let (url, mock) = mock_server().await;
+        let client = AzkabanClient::new(&url, "azkaban", "secret");
+
+        let flow = client.fetch_exec_flow("304").await?;
+        assert_eq!(flow.flow_id, "daily");
+        assert_eq!(flow.nodes[1].attempt, 1);
+
+        let log = client.fetch_exec_job_logs("304", "b", 1, 1024).await?;
+        assert_eq!(log, "line 1\nline 2\n");
+
+        let executions = client
+            .fetch_flow_executions("warehouse", "daily", 0, 10)
+            .await?;
+        assert_eq!(executions[0].exec_id, 305);
+
+        let schedule = client.fetch_schedule("7", "daily").await?;
+        assert_eq!(schedule.unwrap().schedule_id, "3");
+        assert!(client.fetch_schedule("7", "hourly").await?.is_none());
+
+        assert!(client.cancel_flow("304").await.is_err());
+        assert_eq!(mock.lock().unwrap().logins, 1);
+
+        // the session expires on the server, the client logs in again
+        mock.lock().unwrap().logins += 1;
+        let exec_id = client
+            .execute_flow("warehouse", "daily", &["a".to_string()])
+            .await?;
+        assert_eq!(exec_id, "305");
+
+        let mock = mock.lock().unwrap();
+        assert_eq!(mock.logins, 3);
+        let last = mock.requests.last().unwrap();
+        assert_eq!(last["disabled"], "[\"a\"]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_tail() -> anyhow::Result<()> {
+        let (url, mock) = mock_server().await;
+        let client = AzkabanClient::new(&url, "azkaban", "secret");
+        let log: String = (0..5000).map(|i| format!("line {}\n", i)).collect();
+        mock.lock().unwrap().log = log.clone();
+
+        let tail = client.fetch_exec_job_logs("304", "b", 1, 100).await?;
+        assert_eq!(tail, log[log.len() - 100..]);
+
+        // found the end without reading the log through
+        let mock = mock.lock().unwrap();
+        let read: usize = mock
+            .requests
+            .iter()
+            .map(|p| {
+                let offset: usize = p["offset"].parse().unwrap();
+                let length: usize = p["length"].parse().unwrap();
+                (offset + length).min(log.len()).saturating_sub(offset)
+            })
+            .sum();
+        assert!(read < 1000, "read {} bytes", read);
+        assert!(mock.requests.len() < 40, "{} requests", mock.requests.len());
+        Ok(())
+    }
+}
@@ -1,15 +1,673 @@
-use crate::utli::format_duration_chinese;
+use crate::utli::{glob_match, status_category};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// project -> flow -> tasks
+pub type ProjectTasks = HashMap<String, HashMap<String, Vec<Task>>>;
+
 #[derive(Deserialize, Clone, Serialize, Debug)]
 pub struct InitConfig {
-    pub feishu_url: Vec<String>,
+    pub feishu_url: Vec<FeishuUrl>,
     pub target_cron_dir: Vec<String>,
+    /// owner directory, `.toml`, `.yaml` or `.json`, anything else is the legacy csv
     pub mapping_file: String,
+    /// empty to poll through `azkaban_api` only
+    #[serde(default)]
     pub db_full_url: String,
+    /// Azkaban web UI, e.g. `https://azkaban.example.com`
+    #[serde(default)]
+    pub azkaban_url: Option<String>,
+    /// Git web UI of the cron repo, when it can't be derived from the `origin` remote
+    #[serde(default)]
+    pub git_web_url: Option<String>,
+    #[serde(default)]
+    pub fallback: Fallback,
+    /// named webhooks for `routes`
+    #[serde(default)]
+    pub channels: HashMap<String, FeishuUrl>,
+    /// first matching route decides the channels, tasks matching none go to `feishu_url`
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub retry: Retry,
+    /// channel type (`FeishuUrl::kind`) -> limits, shared by all sends to one url
+    #[serde(default = "default_rate_limits")]
+    pub rate_limits: HashMap<String, Vec<RateLimit>>,
+    #[serde(default)]
+    pub card: CardLimits,
+    #[serde(default)]
+    pub digest: Digest,
+    #[serde(default)]
+    pub state: StateConfig,
+    /// card buttons and the server receiving their callbacks, `azmonitor serve`
+    #[serde(default)]
+    pub callback: Option<Callback>,
+    /// Azkaban web API, the url defaults to `azkaban_url`
+    #[serde(default)]
+    pub azkaban_api: Option<AzkabanApi>,
+    /// automatic reruns through `azkaban_api`, the first matching policy applies
+    #[serde(default)]
+    pub reruns: Vec<RerunPolicy>,
+    /// maintenance windows, more are added with `azmonitor silence add`
+    #[serde(default)]
+    pub silences: Vec<Silence>,
+    /// silences added from the cli / api and what was silenced
+    #[serde(default = "default_silence_store")]
+    pub silence_store: String,
+    #[serde(default)]
+    pub flapping: Flapping,
+    /// who else hears about an alert nobody acked, the first matching policy applies
+    #[serde(default)]
+    pub escalations: Vec<Escalation>,
+    /// on-call rotations, an owner of `oncall:<name>` is whoever is on call in `<name>`
+    #[serde(default)]
+    pub rotations: HashMap<String, Rotation>,
+    /// feishu app looking up authors missing from the owner directory by email
+    #[serde(default)]
+    pub contact: Option<Contact>,
+    #[serde(default)]
+    pub identities: Identities,
+    /// team name -> team, routes and matchers can use the name as `team`
+    #[serde(default)]
+    pub teams: HashMap<String, Team>,
+    #[serde(default)]
+    pub team_report: TeamReport,
+    /// Azkaban installations polled side by side, unset fields come from the
+    /// top level; empty polls the top level one alone
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+    /// first matching rule decides the severity of a task, `warning` when none does
+    #[serde(default)]
+    pub severities: Vec<SeverityRule>,
+}
+
+#[derive(Deserialize, Clone, Copy, Serialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// never waits for a digest, red card
+    Critical,
+    #[default]
+    Warning,
+    /// never escalated, grey card
+    Info,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct SeverityRule {
+    #[serde(flatten)]
+    pub matcher: Matcher,
+    pub severity: Severity,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct Instance {
+    /// shown on its cards, routes and silences match it as `instance`
+    pub name: String,
+    pub db_full_url: Option<String>,
+    pub azkaban_url: Option<String>,
+    pub azkaban_api: Option<AzkabanApi>,
+    pub target_cron_dir: Vec<String>,
+    /// default channels of the instance instead of the top level `feishu_url`
+    pub feishu_url: Vec<FeishuUrl>,
+    /// tried before the top level routes
+    pub routes: Vec<Route>,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct Team {
+    /// names or feishu ids, on top of the `team` of people in the owner directory
+    pub members: Vec<String>,
+    /// @ on team reports and on team-owned alerts nobody else owns, may be `oncall:<rotation>`
+    pub lead: Option<String>,
+    /// projects, flows or jobs the team owns whoever wrote them
+    pub owns: Vec<Matcher>,
+    /// channel for the team's alerts and reports
+    pub channel: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct TeamReport {
+    /// weekly failure load per team, like `Mon 09:30`
+    pub report_at: Option<String>,
+    /// how far back the report looks
+    pub window: String,
+}
+
+impl Default for TeamReport {
+    fn default() -> Self {
+        TeamReport {
+            report_at: None,
+            window: "7d".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct Identities {
+    /// blamed name or email -> the name the owner directory knows, applied after `.mailmap`
+    pub aliases: HashMap<String, String>,
+    /// weekly list of blamed authors nobody was found for, like `Mon 09:30`,
+    /// sent to `fallback.unowned_channel`
+    pub report_at: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct Contact {
+    #[serde(default = "default_open_url")]
+    pub url: String,
+    pub app_id: String,
+    pub app_secret: String,
+    /// looked up ids by email, found or not
+    #[serde(default = "default_contact_cache")]
+    pub cache: String,
+    /// how long a cached lookup is trusted
+    #[serde(default = "default_contact_ttl")]
+    pub ttl: String,
+}
+
+fn default_open_url() -> String {
+    "https://open.feishu.cn".to_string()
+}
+
+fn default_contact_cache() -> String {
+    "contacts.json".to_string()
+}
+
+fn default_contact_ttl() -> String {
+    "7d".to_string()
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Rotation {
+    /// names from the mapping file or feishu ids, in rotation order
+    pub members: Vec<String>,
+    /// day of the first handoff, to `members[0]`, e.g. `2025-07-28`
+    pub start: String,
+    #[serde(default = "default_handoff")]
+    pub handoff: String,
+    /// humantime, `7d` for weekly rotations
+    #[serde(default = "default_rotation_length")]
+    pub length: String,
+    /// IANA name like `Asia/Shanghai`, the local timezone when absent
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub overrides: Vec<Override>,
+}
+
+fn default_handoff() -> String {
+    "10:00".to_string()
+}
+
+fn default_rotation_length() -> String {
+    "7d".to_string()
+}
+
+/// `member` is on call from `start` to `end` instead, times as in `Silence`.
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Override {
+    pub member: String,
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Escalation {
+    #[serde(flatten)]
+    pub matcher: Matcher,
+    /// after the owner, in order
+    pub tiers: Vec<Tier>,
+}
+
+/// Notified once an alert stayed unacked for `after` since the owner got it.
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Tier {
+    pub after: String,
+    /// names, feishu ids, `oncall:<rotation>` or `team:<name>` for its lead,
+    /// @ in the chats the alert went to so far
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// names from `channels`
+    #[serde(default)]
+    pub channels: Vec<String>,
+    /// the project's `fallback.team_channels` entry
+    #[serde(default)]
+    pub team_channel: bool,
+    /// @all in the channels of this tier
+    #[serde(default)]
+    pub at_all: bool,
+}
+
+/// A job is flapping when it failed at least `threshold` of its runs in one of
+/// the `windows`, with `min_runs` runs and at least one success there.
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct Flapping {
+    pub enabled: bool,
+    pub windows: Vec<String>,
+    pub min_runs: usize,
+    pub threshold: f64,
+    /// runs shown in the ✅/❌ history
+    pub history: usize,
+    /// weekly flaky job report to the owners, e.g. `Mon 09:30` local time
+    pub report_at: Option<String>,
+}
+
+impl Default for Flapping {
+    fn default() -> Self {
+        Flapping {
+            enabled: false,
+            windows: vec!["7d".to_string()],
+            min_runs: 5,
+            threshold: 0.2,
+            history: 10,
+            report_at: None,
+        }
+    }
+}
+
+fn default_silence_store() -> String {
+    "silences.json".to_string()
+}
+
+/// Alerts matching `matcher` between `start` and `end` are recorded but not sent.
+/// Times are RFC 3339 or `2025-08-01 02:00` in local time.
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Silence {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub matcher: Matcher,
+    /// active right away when absent
+    #[serde(default)]
+    pub start: Option<String>,
+    pub end: String,
+    pub reason: String,
+    #[serde(default)]
+    pub created_by: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RerunMode {
+    /// the whole flow again
+    #[default]
+    Flow,
+    /// only what did not succeed, the succeeded jobs are disabled
+    Failed,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct RerunPolicy {
+    #[serde(flatten)]
+    pub matcher: Matcher,
+    /// reruns per alert, until the job succeeds again
+    #[serde(default = "default_max_reruns")]
+    pub max_reruns: usize,
+    /// wait after the failure before rerunning
+    #[serde(default = "default_rerun_delay")]
+    pub delay: String,
+    #[serde(default)]
+    pub mode: RerunMode,
+}
+
+fn default_max_reruns() -> usize {
+    1
+}
+
+fn default_rerun_delay() -> String {
+    "5m".to_string()
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct Callback {
+    /// e.g. `0.0.0.0:8088`
+    pub listen: String,
+    /// Verification Token of the Feishu app
+    pub verification_token: String,
+    /// Encrypt Key of the Feishu app, checks `X-Lark-Signature` when set
+    #[serde(default)]
+    pub encrypt_key: Option<String>,
+    /// bearer token for the `/silences` api, the api is off without it
+    #[serde(default)]
+    pub api_token: Option<String>,
+    #[serde(default = "default_snooze")]
+    pub snooze: String,
+}
+
+fn default_snooze() -> String {
+    "4h".to_string()
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct AzkabanApi {
+    #[serde(default)]
+    pub url: Option<String>,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct StateConfig {
+    /// open alerts between runs
+    pub path: String,
+    /// tell the owner when a failed job succeeded again
+    pub notify_resolved: bool,
+    /// drop alerts whose job hasn't failed for this long (humantime, `7d`)
+    pub expire_after: String,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            path: "alert_state.json".to_string(),
+            notify_resolved: true,
+            expire_after: "7d".to_string(),
+        }
+    }
+}
+
+/// Hold alerts and send one card per owner/channel when `window` has passed since the
+/// first held alert, or at the local times in `at` ("08:30").
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct Digest {
+    pub enabled: bool,
+    pub window: Option<String>,
+    pub at: Vec<String>,
+    /// held alerts between runs
+    pub store: String,
+    /// still sent right away
+    pub immediate: Vec<Matcher>,
+}
+
+impl Default for Digest {
+    fn default() -> Self {
+        Digest {
+            enabled: false,
+            window: None,
+            at: vec![],
+            store: "digest.json".to_string(),
+            immediate: vec![],
+        }
+    }
+}
+
+/// Cards over `max_bytes` or `max_elements` are split by project/flow,
+/// more than `summary_threshold` tasks are sent as a compact summary table.
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct CardLimits {
+    pub max_bytes: usize,
+    pub max_elements: usize,
+    pub summary_threshold: usize,
+}
+
+impl Default for CardLimits {
+    fn default() -> Self {
+        // Feishu bots take 30KB and 200 components, keep some room for the signature
+        CardLimits {
+            max_bytes: 28 * 1024,
+            max_elements: 180,
+            summary_threshold: 40,
+        }
+    }
+}
+
+/// Webhook delivery retries, durations in humantime format like `500ms` or `24h`.
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(default)]
+pub struct Retry {
+    pub max_attempts: u32,
+    pub base_delay: String,
+    pub max_delay: String,
+    /// messages that still fail are written here and replayed on the next run
+    pub spool_dir: String,
+    /// spooled messages older than this are dropped
+    pub spool_max_age: String,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_attempts: 4,
+            base_delay: "1s".to_string(),
+            max_delay: "30s".to_string(),
+            spool_dir: "spool".to_string(),
+            spool_max_age: "24h".to_string(),
+        }
+    }
+}
+
+/// Glob patterns over a task, an absent field matches everything.
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Matcher {
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub flow: Option<String>,
+    #[serde(default)]
+    pub job: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// see `Task::category`
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub instance: Option<String>,
+    /// `critical`, `warning` or `info`
+    #[serde(default)]
+    pub severity: Option<String>,
+}
+
+impl Matcher {
+    pub fn matches(&self, task: &Task) -> bool {
+        let hit = |pattern: &Option<String>, value: &str| {
+            pattern.as_deref().is_none_or(|p| glob_match(p, value))
+        };
+
+        hit(&self.project, &task.project_name)
+            && hit(&self.flow, &task.flow_id)
+            && hit(&self.job, &task.job_id)
+            && hit(&self.owner, &task.owner)
+            && hit(&self.category, task.category())
+            && hit(&self.team, task.team.as_deref().unwrap_or_default())
+            && hit(&self.instance, task.instance.as_deref().unwrap_or_default())
+            && hit(&self.severity, task.severity.name())
+    }
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Route {
+    #[serde(flatten)]
+    pub matcher: Matcher,
+    /// none for a route that only sets the assignee, the tasks then go where
+    /// they would without it
+    #[serde(default)]
+    pub channels: Vec<String>,
+    /// @ this one instead of the resolved owner: a name, a feishu id or `oncall:<rotation>`
+    #[serde(default)]
+    pub assignee: Option<String>,
+    /// keep matching the following routes as well
+    #[serde(default, rename = "continue")]
+    pub continue_matching: bool,
+}
+
+/// Where alerts go when no owner could be resolved for a task, tried in order.
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Fallback {
+    /// project -> feishu id of the default owner
+    #[serde(default)]
+    pub project_owners: HashMap<String, String>,
+    /// project -> webhook of the team channel
+    #[serde(default)]
+    pub team_channels: HashMap<String, FeishuUrl>,
+    /// catch-all channel for unowned jobs
+    #[serde(default)]
+    pub unowned_channel: Option<FeishuUrl>,
+}
+
+/// A bot webhook, either a bare url or a url with the bot's signing secret
+/// and the channel type used to pick its `rate_limits`.
+#[derive(Deserialize, Clone, Serialize, Debug)]
+#[serde(untagged)]
+pub enum FeishuUrl {
+    Plain(String),
+    Detailed {
+        url: String,
+        #[serde(default)]
+        secret: Option<String>,
+        #[serde(default)]
+        kind: Option<String>,
+    },
+}
+
+/// At most `requests` sends per `per` (humantime, `1s`, `1m`)
+#[derive(Deserialize, Clone, Serialize, Debug)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: String,
+}
+
+fn default_rate_limits() -> HashMap<String, Vec<RateLimit>> {
+    let feishu = vec![
+        RateLimit {
+            requests: 5,
+            per: "1s".to_string(),
+        },
+        RateLimit {
+            requests: 100,
+            per: "1m".to_string(),
+        },
+    ];
+    HashMap::from([("feishu".to_string(), feishu)])
+}
+
+impl InitConfig {
+    /// Every webhook the config knows about, used to find the secret of a spooled url.
+    pub fn webhooks(&self) -> Vec<&FeishuUrl> {
+        let mut all: Vec<&FeishuUrl> = self.feishu_url.iter().collect();
+        all.extend(self.channels.values());
+        all.extend(self.fallback.team_channels.values());
+        all.extend(self.fallback.unowned_channel.iter());
+        all.extend(self.instances.iter().flat_map(|i| i.feishu_url.iter()));
+        all
+    }
+
+    pub fn webhook_by_url(&self, url: &str) -> Option<&FeishuUrl> {
+        self.webhooks().into_iter().find(|w| w.url() == url)
+    }
+
+    pub fn instance(&self, name: &str) -> Option<&Instance> {
+        self.instances.iter().find(|i| i.name == name)
+    }
+
+    /// The config one instance runs with. Its alert state and digest live in
+    /// their own files, e.g. `alert_state.prod.json`.
+    pub fn for_instance(&self, instance: &Instance) -> InitConfig {
+        let mut config = self.clone();
+        if let Some(url) = &instance.db_full_url {
+            config.db_full_url.clone_from(url);
+        }
+        if instance.azkaban_url.is_some() {
+            config.azkaban_url.clone_from(&instance.azkaban_url);
+        }
+        if instance.azkaban_api.is_some() {
+            config.azkaban_api.clone_from(&instance.azkaban_api);
+        }
+        if !instance.target_cron_dir.is_empty() {
+            config.target_cron_dir.clone_from(&instance.target_cron_dir);
+        }
+        if !instance.feishu_url.is_empty() {
+            config.feishu_url.clone_from(&instance.feishu_url);
+        }
+        config.routes = instance
+            .routes
+            .iter()
+            .chain(&self.routes)
+            .cloned()
+            .collect();
+        config.state.path = with_suffix(&self.state.path, &instance.name);
+        config.digest.store = with_suffix(&self.digest.store, &instance.name);
+        config
+    }
+}
+
+/// `alert_state.json` -> `alert_state.<suffix>.json`
+fn with_suffix(path: &str, suffix: &str) -> String {
+    let p = Path::new(path);
+    let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or(path);
+    let name = match p.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, suffix, ext),
+        None => format!("{}.{}", stem, suffix),
+    };
+    p.with_file_name(name).to_string_lossy().to_string()
+}
+
+impl FeishuUrl {
+    pub fn url(&self) -> &str {
+        match self {
+            FeishuUrl::Plain(url) => url,
+            FeishuUrl::Detailed { url, .. } => url,
+        }
+    }
+
+    pub fn kind(&self) -> &str {
+        match self {
+            FeishuUrl::Detailed {
+                kind: Some(kind), ..
+            } => kind,
+            _ => "feishu",
+        }
+    }
+
+    pub fn secret(&self) -> Option<&str> {
+        match self {
+            FeishuUrl::Plain(_) => None,
+            FeishuUrl::Detailed { secret, .. } => secret.as_deref().filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+/// Why a task ended up without an owner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Unowned {
+    /// no job with this project/flow/job in the cron dirs
+    JobNotFound,
+    /// git blame gave no author for the job lines
+    BlameEmpty,
+    /// the blamed author is not in the mapping file
+    MappingMissing(String),
+}
+
+impl Unowned {
+    pub fn describe(&self) -> String {
+        match self {
+            Unowned::JobNotFound => "cron 目录中找不到该任务".to_string(),
+            Unowned::BlameEmpty => "git blame 没有找到作者".to_string(),
+            Unowned::MappingMissing(author) => format!("作者 {} 不在映射文件中", author),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +677,7 @@ pub struct Task {
     pub flow_id: String,
     pub job_id: String,
     pub attempt: u8,
+    pub status: i32,
     pub owner: String,
     pub input_params: String,
     pub output_params: String,
@@ -26,6 +685,43 @@ pub struct Task {
     pub end_time: DateTime<Utc>,
     pub duration: Duration,
     pub desc: String,
+    pub log_excerpt: String,
+    pub source_url: Option<String>,
+    pub unowned: Option<Unowned>,
+    #[serde(default)]
+    pub team: Option<String>,
+    /// name of the Azkaban instance it ran on, `None` with a single instance
+    #[serde(default)]
+    pub instance: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+#[cfg(test)]
+impl Task {
+    pub fn demo(project: &str, job_id: &str, owner: &str) -> Task {
+        Task {
+            exec_id: "1001".to_string(),
+            project_name: project.to_string(),
+            flow_id: "daily".to_string(),
+            job_id: job_id.to_string(),
+            attempt: 0,
+            status: 70,
+            owner: owner.to_string(),
+            input_params: "".to_string(),
+            output_params: "".to_string(),
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            duration: Duration::from_secs(90),
+            desc: "".to_string(),
+            log_excerpt: "".to_string(),
+            source_url: None,
+            unowned: None,
+            team: None,
+            instance: None,
+            severity: Severity::Warning,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,7 +733,11 @@ pub struct Job {
     pub other: String,
     pub flow_file: PathBuf,
     pub owner: String,
+    /// email of the blamed author, empty when unknown
+    pub owner_email: String,
     pub desc: String,
+    /// Web link to the `start`-`end` lines of `flow_file`
+    pub source_url: Option<String>,
 }
 
 impl Clone for Job {
@@ -50,7 +750,9 @@ impl Clone for Job {
             other: self.other.clone(),
             flow_file: self.flow_file.clone(),
             owner: self.owner.clone(),
+            owner_email: self.owner_email.clone(),
             desc: self.desc.clone(),
+            source_url: self.source_url.clone(),
         }
     }
 }
@@ -61,26 +763,7 @@ pub struct Configuration {
 }
 
 impl Task {
-    pub fn to_string(&self) -> Result<String, anyhow::Error> {
-        Ok(format!(
-            "
-                **flow_id** : {}\n\
-                **exec_id** : {}\n\
-                **job_id**: {}\n\
-                **attempt**: {}\n\
-                **start_time**: {}\n\
-                **end_time**: {}\n\
-                **duration**: {}\n\
-                ",
-            self.flow_id,
-            self.exec_id,
-            self.job_id,
-            self.attempt,
-            self.start_time,
-            self.end_time,
-            format_duration_chinese(self.duration),
-        )
-        .trim_start()
-        .to_string())
+    pub fn category(&self) -> &'static str {
+        status_category(self.status, self.attempt)
     }
 }
@@ -0,0 +1,523 @@
+//! http server for Feishu card button callbacks, started by `azmonitor serve`
+
+use crate::azkaban::AzkabanClient;
+use crate::bean::{Callback, InitConfig, ProjectTasks};
+use crate::notice::{card_id, render_card, tag_instance, CardOptions};
+use crate::silence;
+use crate::state;
+use crate::state::AlertState;
+use crate::utli::{lock_file, parse_duration};
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hyper::body::to_bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// how far the signed timestamp of a callback may be from now
+const MAX_SKEW: Duration = Duration::from_secs(5 * 60);
+
+pub async fn serve() -> Result<()> {
+    let config = InitConfig::global();
+    let callback = config
+        .callback
+        .as_ref()
+        .ok_or_else(|| anyhow!("callback is not configured"))?;
+    let addr: SocketAddr = callback.listen.parse()?;
+
+    let make = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    println!("Listening for card callbacks on {}", addr);
+    Server::bind(&addr).serve(make).await?;
+
+    Ok(())
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let headers = req.headers().clone();
+    let body = match to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(e) => {
+            return Ok(reply(
+                StatusCode::BAD_REQUEST,
+                json!({"msg": e.to_string()}),
+            ))
+        }
+    };
+
+    if path.starts_with("/silences") {
+        return Ok(silences_api(&method, &path, &headers, &body).await);
+    }
+    if method != Method::POST {
+        return Ok(reply(
+            StatusCode::METHOD_NOT_ALLOWED,
+            json!({"msg": "POST only"}),
+        ));
+    }
+
+    match on_callback(&headers, &body).await {
+        Ok(v) => Ok(reply(StatusCode::OK, v)),
+        Err(e) => {
+            println!("Error: Card callback rejected: {}", e);
+            Ok(reply(
+                StatusCode::BAD_REQUEST,
+                json!({"msg": e.to_string()}),
+            ))
+        }
+    }
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    let mut res = Response::new(Body::from(body.to_string()));
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert("Content-Type", "application/json".parse().unwrap());
+    res
+}
+
+async fn on_callback(headers: &HeaderMap, body: &[u8]) -> Result<Value> {
+    let config = InitConfig::global();
+    let callback = config
+        .callback
+        .as_ref()
+        .ok_or_else(|| anyhow!("callback is not configured"))?;
+
+    let req = open_request(callback, headers, body, Utc::now())?;
+
+    // url_verification when the request url is saved in the developer console
+    if req["type"] == "url_verification" {
+        check_token(callback, &req["token"])?;
+        return Ok(json!({"challenge": req["challenge"]}));
+    }
+
+    check_token(callback, &req["header"]["token"])?;
+    if req["header"]["event_type"] != "card.action.trigger" {
+        return Err(anyhow!("Unexpected event {}", req["header"]["event_type"]));
+    }
+
+    let event = &req["event"];
+    let operator = event["operator"]["open_id"].as_str().unwrap_or_default();
+    let value = &event["action"]["value"];
+    let action = value["action"].as_str().unwrap_or_default();
+    let key = value["key"].as_str().unwrap_or_default();
+    let card_ref = value["card"].as_str().unwrap_or_default();
+    let user_id = value["user_id"].as_str().unwrap_or_default();
+
+    // state file and Azkaban api of the instance the card came from
+    let instance = value["instance"].as_str();
+    let config = match instance {
+        Some(name) => config.for_instance(
+            config
+                .instance(name)
+                .ok_or_else(|| anyhow!("Unknown instance {}", name))?,
+        ),
+        None => config.clone(),
+    };
+    let state_path = Path::new(&config.state.path);
+    let _lock = lock_file(state_path).await?;
+    let mut alerts = state::load(state_path).await?;
+    // every task on the clicked card, the monitor wrote them down when sending
+    let keys = match alerts.cards.get(card_ref) {
+        Some(keys) => keys.clone(),
+        None if !key.is_empty() => vec![key.to_string()],
+        None => return Err(anyhow!("Unknown card {}", card_ref)),
+    };
+
+    let toast = if action == "rerun" {
+        let alert = alerts
+            .alerts
+            .get(key)
+            .ok_or_else(|| anyhow!("{} is already resolved", key))?;
+        let exec_id = AzkabanClient::from_config(&config)?
+            .execute_flow(&alert.task.project_name, &alert.task.flow_id, &[])
+            .await?;
+        println!(
+            "{} reran {}.{} as {}",
+            operator, alert.task.project_name, alert.task.flow_id, exec_id
+        );
+        format!("已重跑 {}, 执行 {}", alert.task.flow_id, exec_id)
+    } else if action == "ack_all" {
+        let acked: Vec<&String> = keys
+            .iter()
+            .filter(|k| alerts.alerts.contains_key(*k))
+            .collect();
+        for k in &acked {
+            apply_action(&mut alerts, "ack", k, operator, Utc::now(), Duration::ZERO)?;
+        }
+        println!(
+            "{} acked {} alerts of card {}",
+            operator,
+            acked.len(),
+            card_ref
+        );
+        format!("已确认 {} 个任务", acked.len())
+    } else {
+        let snooze = parse_duration(&callback.snooze)?;
+        let toast = apply_action(&mut alerts, action, key, operator, Utc::now(), snooze)?;
+        println!("{} {} {}", operator, action, key);
+        toast
+    };
+
+    // resolved tasks drop off the card and change its id, the rest stay findable
+    let open: Vec<String> = keys
+        .iter()
+        .filter(|k| alerts.alerts.contains_key(*k))
+        .cloned()
+        .collect();
+    alerts
+        .cards
+        .entry(card_id(&open))
+        .or_insert_with(|| keys.clone());
+    state::save(state_path, &alerts).await?;
+
+    let mut card = render_from_state(&config, instance, &alerts, user_id, &keys).await;
+    tag_instance(&mut card, instance);
+
+    Ok(json!({
+        "toast": {"type": "success", "content": toast},
+        "card": {"type": "raw", "data": card["card"]}
+    }))
+}
+
+/// `GET /silences`, `POST /silences` with a silence as body, `DELETE /silences/<id>`
+async fn silences_api(
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Response<Body> {
+    let config = InitConfig::global();
+    let token = config
+        .callback
+        .as_ref()
+        .and_then(|c| c.api_token.as_deref());
+    let Some(token) = token.filter(|t| !t.is_empty()) else {
+        return reply(
+            StatusCode::NOT_FOUND,
+            json!({"msg": "api_token is not configured"}),
+        );
+    };
+    let auth = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if auth.strip_prefix("Bearer ") != Some(token) {
+        return reply(StatusCode::UNAUTHORIZED, json!({"msg": "Bad token"}));
+    }
+
+    match on_silences(config, method, path, body).await {
+        Ok(v) => reply(StatusCode::OK, v),
+        Err(e) => reply(StatusCode::BAD_REQUEST, json!({"msg": e.to_string()})),
+    }
+}
+
+async fn on_silences(
+    config: &InitConfig,
+    method: &Method,
+    path: &str,
+    body: &[u8],
+) -> Result<Value> {
+    let store_path = Path::new(&config.silence_store);
+    let _lock = lock_file(store_path).await?;
+    let mut store = silence::load(store_path).await?;
+    let now = Utc::now();
+
+    match (method, path.trim_end_matches('/')) {
+        (&Method::GET, "/silences") => {
+            let list: Vec<Value> = silence::all(config, &store)
+                .into_iter()
+                .map(|s| json!({"key": s.key(), "active": s.active(now), "silence": s}))
+                .collect();
+            Ok(json!({"silences": list}))
+        }
+        (&Method::POST, "/silences") => {
+            let silence = silence::normalize(serde_json::from_slice(body)?, now)?;
+            println!("Silence {} added: {}", silence.key(), silence.reason);
+            store.silences.push(silence.clone());
+            silence::save(store_path, &store).await?;
+            Ok(json!({"silence": silence}))
+        }
+        (&Method::DELETE, p) => {
+            let id = p
+                .strip_prefix("/silences/")
+                .ok_or_else(|| anyhow!("DELETE /silences/<id>"))?;
+            let before = store.silences.len();
+            store.silences.retain(|s| s.id.as_deref() != Some(id));
+            if store.silences.len() == before {
+                return Err(anyhow!("No stored silence {}", id));
+            }
+            silence::save(store_path, &store).await?;
+            Ok(json!({"removed": id}))
+        }
+        _ => Err(anyhow!("Unsupported {} {}", method, path)),
+    }
+}
+
+/// Check the signature and decrypt, returns the plain callback body. With an
+/// encrypt key every request must be encrypted, and all but url_verification
+/// signed less than `MAX_SKEW` ago.
+fn open_request(
+    callback: &Callback,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Result<Value> {
+    let Some(encrypt_key) = callback.encrypt_key.as_deref().filter(|k| !k.is_empty()) else {
+        return Ok(serde_json::from_slice(body)?);
+    };
+
+    let outer: Value = serde_json::from_slice(body)?;
+    let encrypted = outer["encrypt"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Callback is not encrypted"))?;
+    let req: Value = serde_json::from_slice(&decrypt(encrypt_key, encrypted)?)?;
+
+    // the console does not sign url_verification requests
+    if req["type"] == "url_verification" {
+        return Ok(req);
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| anyhow!("Missing {}", name))
+    };
+    let timestamp = header("X-Lark-Request-Timestamp")?;
+    let expected = header("X-Lark-Signature")?;
+    let actual = signature(
+        timestamp,
+        header("X-Lark-Request-Nonce")?,
+        encrypt_key,
+        body,
+    );
+    if !constant_eq(expected.as_bytes(), actual.as_bytes()) {
+        return Err(anyhow!("Bad signature"));
+    }
+
+    let sent = timestamp
+        .parse::<i64>()
+        .map_err(|_| anyhow!("Bad timestamp {}", timestamp))?;
+    if (now.timestamp() - sent).abs() > MAX_SKEW.as_secs() as i64 {
+        return Err(anyhow!("Stale request, sent at {}", timestamp));
+    }
+
+    Ok(req)
+}
+
+/// Compare without returning early, so the time taken does not tell how much matched.
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn check_token(callback: &Callback, token: &Value) -> Result<()> {
+    if token.as_str() != Some(callback.verification_token.as_str()) {
+        return Err(anyhow!("Bad verification token"));
+    }
+    Ok(())
+}
+
+/// sha256 hex of timestamp + nonce + encrypt key + body
+fn signature(timestamp: &str, nonce: &str, encrypt_key: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(timestamp.as_bytes());
+    hasher.update(nonce.as_bytes());
+    hasher.update(encrypt_key.as_bytes());
+    hasher.update(body);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// AES-256-CBC keyed by sha256 of the encrypt key, the first block is the iv.
+fn decrypt(encrypt_key: &str, encrypted: &str) -> Result<Vec<u8>> {
+    let data = STANDARD.decode(encrypted)?;
+    if data.len() < 32 {
+        return Err(anyhow!("Encrypted body too short"));
+    }
+    let key = Sha256::digest(encrypt_key.as_bytes());
+    let (iv, data) = data.split_at(16);
+
+    Aes256CbcDec::new(&key, iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|e| anyhow!("Can't decrypt callback: {}", e))
+}
+
+/// Ack or snooze an alert, returns the toast to show.
+fn apply_action(
+    alerts: &mut AlertState,
+    action: &str,
+    key: &str,
+    operator: &str,
+    now: DateTime<Utc>,
+    snooze: Duration,
+) -> Result<String> {
+    let alert = alerts
+        .alerts
+        .get_mut(key)
+        .ok_or_else(|| anyhow!("{} is already resolved", key))?;
+
+    match action {
+        "ack" => {
+            alert.acked_by = Some(operator.to_string());
+            alert.acked_at = Some(now);
+            Ok(format!("已确认 {}", alert.task.job_id))
+        }
+        "snooze" => {
+            alert.snoozed_until = Some(now + chrono::Duration::from_std(snooze)?);
+            alert.snoozed_by = Some(operator.to_string());
+            Ok(format!(
+                "{} 暂停提醒 {}",
+                alert.task.job_id,
+                humantime::format_duration(snooze)
+            ))
+        }
+        _ => Err(anyhow!("Unknown action {:?}", action)),
+    }
+}
+
+/// The card again with the tasks still open, showing who acked or snoozed them.
+async fn render_from_state(
+    config: &InitConfig,
+    instance: Option<&str>,
+    alerts: &AlertState,
+    user_id: &str,
+    keys: &[String],
+) -> Value {
+    let mut opts = CardOptions::from_config(config);
+    opts.instance = instance.map(String::from);
+    let mut details: ProjectTasks = Default::default();
+
+    for key in keys {
+        let Some(alert) = alerts.alerts.get(key) else {
+            opts.notes
+                .entry(key.clone())
+                .or_default()
+                .push("已恢复".to_string());
+            continue;
+        };
+        let t = &alert.task;
+        details
+            .entry(t.project_name.clone())
+            .or_default()
+            .entry(t.flow_id.clone())
+            .or_default()
+            .push(t.clone());
+        let notes = alert.notes(opts.azkaban_url.as_deref());
+        opts.notes.insert(key.clone(), notes);
+    }
+
+    render_card(user_id, &details, &opts).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{Callback, Task};
+    use crate::callback::{apply_action, open_request, signature};
+    use crate::state::{alert_key, AlertState};
+    use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use chrono::{TimeZone, Utc};
+    use hyper::HeaderMap;
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+
+    type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+    #[test]
+    fn test_apply_action() {
+        let mut alerts = AlertState::default();
+        let task = Task::demo("warehouse", "a", "ou_a");
+        let now = Utc::now();
+        alerts.observe(&task, now);
+        let key = alert_key(&task);
+        let snooze = Duration::from_secs(4 * 3600);
+
+        assert!(apply_action(&mut alerts, "ack", "missing", "ou_b", now, snooze).is_err());
+        assert!(apply_action(&mut alerts, "drop", &key, "ou_b", now, snooze).is_err());
+
+        apply_action(&mut alerts, "snooze", &key, "ou_b", now, snooze).unwrap();
+        let alert = &alerts.alerts[&key];
+        assert_eq!(alert.snoozed_by.as_deref(), Some("ou_b"));
+        assert!(alert.muted(now + chrono::Duration::hours(3)));
+        assert!(!alert.muted(now + chrono::Duration::hours(5)));
+
+        let toast = apply_action(&mut alerts, "ack", &key, "ou_c", now, snooze).unwrap();
+        assert!(toast.contains("a"));
+        assert_eq!(alerts.alerts[&key].acked_by.as_deref(), Some("ou_c"));
+    }
+
+    #[test]
+    fn test_open_encrypted_request() {
+        let callback = Callback {
+            listen: "127.0.0.1:0".to_string(),
+            verification_token: "v".to_string(),
+            encrypt_key: Some("secret".to_string()),
+            api_token: None,
+            snooze: "4h".to_string(),
+        };
+        let encrypt = |plain: &[u8]| {
+            let key = Sha256::digest(b"secret");
+            let iv = [7u8; 16];
+            let mut data = iv.to_vec();
+            data.extend(Aes256CbcEnc::new(&key, &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plain));
+            format!(r#"{{"encrypt":"{}"}}"#, STANDARD.encode(data))
+        };
+        let sent = Utc.timestamp_opt(1700000000, 0).unwrap();
+
+        // url_verification is not signed
+        let body = encrypt(br#"{"type":"url_verification","token":"v","challenge":"c"}"#);
+        let req = open_request(&callback, &HeaderMap::new(), body.as_bytes(), sent).unwrap();
+        assert_eq!(req["challenge"], "c");
+
+        let plain = br#"{"header":{"token":"v","event_type":"card.action.trigger"}}"#;
+        let body = encrypt(plain);
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Lark-Request-Timestamp", "1700000000".parse().unwrap());
+        headers.insert("X-Lark-Request-Nonce", "n".parse().unwrap());
+        let open =
+            |headers: &HeaderMap, body: &[u8], now| open_request(&callback, headers, body, now);
+        let err = open(&headers, body.as_bytes(), sent).unwrap_err();
+        assert!(err.to_string().contains("X-Lark-Signature"), "{}", err);
+
+        let sig = signature("1700000000", "n", "secret", body.as_bytes());
+        headers.insert("X-Lark-Signature", sig.parse().unwrap());
+        let req = open(
+            &headers,
+            body.as_bytes(),
+            sent + chrono::Duration::minutes(1),
+        )
+        .unwrap();
+        assert_eq!(req["header"]["token"], "v");
+
+        let stale = open(
+            &headers,
+            body.as_bytes(),
+            sent + chrono::Duration::minutes(10),
+        );
+        assert!(stale.unwrap_err().to_string().contains("Stale"));
+
+        headers.insert("X-Lark-Signature", "bad".parse().unwrap());
+        assert!(open(&headers, body.as_bytes(), sent).is_err());
+
+        // signed but not encrypted
+        let sig = signature("1700000000", "n", "secret", plain);
+        headers.insert("X-Lark-Signature", sig.parse().unwrap());
+        let err = open(&headers, plain, sent).unwrap_err();
+        assert!(err.to_string().contains("not encrypted"), "{}", err);
+    }
+}
@@ -0,0 +1,223 @@
+//! `azmonitor check-config`: the config loads, and what it points at answers
+
+use crate::azkaban::AzkabanClient;
+use crate::bean::InitConfig;
+use crate::directory;
+use crate::gitblame::git;
+use crate::notice::probe;
+use anyhow::{anyhow, Result};
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, OptsBuilder};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+const DB_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One line of the report.
+struct Check {
+    what: String,
+    result: Result<String>,
+}
+
+fn database(url: &str) -> Result<String> {
+    let opts = OptsBuilder::from_opts(Opts::from_url(url)?).tcp_connect_timeout(Some(DB_TIMEOUT));
+    let mut conn = Conn::new(opts)?;
+    let version: Option<String> = conn.query_first("SELECT VERSION()")?;
+    Ok(format!("connected, MySQL {}", version.unwrap_or_default()))
+}
+
+/// Instances without a database are polled through the web api.
+async fn web_api(config: &InitConfig) -> Result<String> {
+    AzkabanClient::from_config(config)?.login().await?;
+    Ok("logged in".to_string())
+}
+
+async fn owners(path: &str) -> Result<String> {
+    let (directory, problems) = directory::load(Path::new(path)).await?;
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "{} people, left out:\n{}",
+            directory.people.len(),
+            problems.join("\n")
+        ));
+    }
+    Ok(format!("{} people", directory.people.len()))
+}
+
+async fn cron_dir(dir: &str) -> Result<String> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+        return Err(anyhow!("not a directory"));
+    }
+    let top = git(path, &["rev-parse", "--show-toplevel"]).await?;
+    Ok(format!("in git repository {}", top))
+}
+
+/// The webhook without its token, the last path segment, but for the end of
+/// it to tell webhooks apart.
+fn masked(url: &str) -> String {
+    let Some((base, token)) = url.trim_end_matches('/').rsplit_once('/') else {
+        return url.to_string();
+    };
+    let chars: Vec<char> = token.chars().collect();
+    let end: String = match chars.len() {
+        n if n > 8 => chars[n - 4..].iter().collect(),
+        _ => String::new(),
+    };
+    format!("{}/****{}", base, end)
+}
+
+/// Every instance the monitor would poll, named as on its cards.
+fn instances(config: &InitConfig) -> Vec<(String, InitConfig)> {
+    if config.instances.is_empty() {
+        return vec![(String::new(), config.clone())];
+    }
+    config
+        .instances
+        .iter()
+        .map(|i| (format!("[{}] ", i.name), config.for_instance(i)))
+        .collect()
+}
+
+async fn checks(config: &InitConfig) -> Vec<Check> {
+    let mut checks = vec![];
+
+    checks.push(Check {
+        what: "git".to_string(),
+        result: git(Path::new("."), &["--version"]).await,
+    });
+
+    for (name, config) in instances(config) {
+        if config.db_full_url.trim().is_empty() {
+            checks.push(Check {
+                what: format!("{}azkaban api", name),
+                result: web_api(&config).await,
+            });
+        } else {
+            checks.push(Check {
+                what: format!("{}database", name),
+                result: database(&config.db_full_url),
+            });
+        }
+        for dir in &config.target_cron_dir {
+            checks.push(Check {
+                what: format!("{}cron dir {}", name, dir),
+                result: cron_dir(dir).await,
+            });
+        }
+    }
+
+    checks.push(Check {
+        what: format!("owner directory {}", config.mapping_file),
+        result: owners(&config.mapping_file).await,
+    });
+
+    let mut seen = HashSet::new();
+    for webhook in config.webhooks() {
+        if !seen.insert(webhook.url()) {
+            continue;
+        }
+        let url = masked(webhook.url());
+        let result = probe(webhook)
+            .await
+            .map_err(|e| anyhow!(e.to_string().replace(webhook.url(), &url)));
+        checks.push(Check {
+            what: format!("webhook {}", url),
+            result,
+        });
+    }
+
+    checks
+}
+
+/// Print what works and what does not, fails when anything does not.
+pub async fn run(config: &InitConfig) -> Result<()> {
+    let checks = checks(config).await;
+
+    let mut failed = 0;
+    for c in &checks {
+        match &c.result {
+            Ok(detail) => println!("ok    {}: {}", c.what, detail),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {}: {}", c.what, e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", failed, checks.len()));
+    }
+    println!("All {} checks passed", checks.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{FeishuUrl, InitConfig};
+    use crate::check::{cron_dir, instances, masked};
+    use crate::notice::probe;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::json;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_probe() {
+        let make = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let res = match req.uri().path() {
+                    "/hook/good" => json!({"code": 19002, "msg": "params error"}),
+                    _ => json!({"code": 19001, "msg": "incoming webhook access token invalid"}),
+                };
+                Ok::<_, Infallible>(Response::new(Body::from(res.to_string())))
+            }))
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let good = FeishuUrl::Plain(format!("{}/hook/good", url));
+        assert_eq!(probe(&good).await.unwrap(), "reachable, code 19002");
+        let bad = FeishuUrl::Plain(format!("{}/hook/bad", url));
+        let err = probe(&bad).await.unwrap_err().to_string();
+        assert!(err.contains("token invalid"), "{}", err);
+    }
+
+    #[test]
+    fn test_masked() {
+        assert_eq!(
+            masked(
+                "https://open.feishu.cn/open-apis/bot/v2/hook/3f2a9c1e-77d0-4b6e-9a51-0c8d2e4f5a6b"
+            ),
+            "https://open.feishu.cn/open-apis/bot/v2/hook/****5a6b"
+        );
+        assert_eq!(masked("https://hook/short/"), "https://hook/****");
+    }
+
+    #[tokio::test]
+    async fn test_cron_dir_and_instances() {
+        assert!(cron_dir("/no/such/dir").await.is_err());
+
+        let config: InitConfig = serde_json::from_value(json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "mysql://a",
+            "instances": [{"name": "prod"}, {"name": "test", "db_full_url": "mysql://b"}]
+        }))
+        .unwrap();
+        let found: Vec<(String, String)> = instances(&config)
+            .into_iter()
+            .map(|(name, c)| (name, c.db_full_url))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("[prod] ".to_string(), "mysql://a".to_string()),
+                ("[test] ".to_string(), "mysql://b".to_string())
+            ]
+        );
+    }
+}
@@ -1,121 +1,270 @@
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
 use crate::bean::InitConfig;
+use crate::directory::{self, Directory};
+use crate::locate::Source;
+use crate::validate::validate;
 use std::fs;
 
 static CONFIG: OnceLock<InitConfig> = OnceLock::new();
 
-pub fn init() -> Result<InitConfig, anyhow::Error> {
-    let pwd = std::env::current_dir()?;
+/// Looked for in `./config` and then `.`, the first one found is used.
+const CONFIG_NAMES: [&str; 5] = [
+    "monitor.toml",
+    "monitor.yaml",
+    "monitor.yml",
+    "monitor.json",
+    "monitor.config",
+];
 
-    println!("pwd : {:?}", pwd);
+/// Prefix of the environment variables overriding config values.
+const ENV_PREFIX: &str = "AZMON_";
+
+/// Config file to use instead of looking for one, overridden by `--config`.
+const CONFIG_ENV: &str = "AZMON_CONFIG";
 
-    let preferred_config_path = pwd.join("config").join("monitor.config");
-    let fallback_config_path = pwd.join("monitor.config");
-
-    let config_path = if preferred_config_path.exists() {
-        preferred_config_path
-    } else if fallback_config_path.exists() {
-        fallback_config_path
-    } else {
-        return Err(anyhow!(
-            "Could not find monitor.config in either:\n- {}\n- {}",
-            preferred_config_path.display(),
-            fallback_config_path.display()
-        )
-        .into());
-
-        // todo
-        //      return Err(
-        //          format!("Not config was foudn")
-        //      ).into();
-        // }
+/// The config from `path`, else from `AZMON_CONFIG`, else the first one found.
+pub fn init(path: Option<&Path>) -> Result<InitConfig, anyhow::Error> {
+    let explicit = path
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from));
+
+    let config_path = match explicit {
+        Some(path) if path.is_file() => path,
+        Some(path) => return Err(anyhow!("Config file {} does not exist", path.display())),
+        None => find_config()?,
     };
 
-    let content = fs::read_to_string(config_path)?;
+    println!("config : {}", config_path.display());
+    load(&config_path, std::env::vars())
+}
 
-    let filter_content: String = content
-        .lines()
-        .filter(|l| !l.trim().starts_with("//") && !l.trim().starts_with("#"))
-        .collect();
+fn find_config() -> Result<PathBuf> {
+    let pwd = std::env::current_dir()?;
 
-    let init_config: InitConfig = serde_json::from_str(&filter_content)?;
+    println!("pwd : {:?}", pwd);
 
-    println!("get the config : {:?}", init_config);
+    let candidates: Vec<PathBuf> = [pwd.join("config"), pwd.clone()]
+        .iter()
+        .flat_map(|dir| CONFIG_NAMES.iter().map(move |name| dir.join(name)))
+        .collect();
 
-    Ok(init_config)
+    match candidates.iter().find(|p| p.exists()) {
+        Some(path) => Ok(path.clone()),
+        None => {
+            let tried: Vec<String> = candidates
+                .iter()
+                .map(|p| format!("- {}", p.display()))
+                .collect();
+            Err(anyhow!(
+                "Could not find a config file, use --config or {}. Tried:\n{}",
+                CONFIG_ENV,
+                tried.join("\n")
+            ))
+        }
+    }
 }
 
-impl InitConfig {
-    pub fn do_parse() -> Result<()> {
-        let config = init()?;
+/// Parse, apply the environment overrides and validate the config at `path`.
+pub fn load(path: &Path, vars: impl IntoIterator<Item = (String, String)>) -> Result<InitConfig> {
+    let content = fs::read_to_string(path)?;
+    let bad = |e: anyhow::Error| anyhow!("Bad config {}:\n{}", path.display(), e);
 
-        CONFIG.set(config).expect("Config already initialized");
+    let mut config = parse(&content, path).map_err(bad)?;
+    config = apply_env(config, vars).map_err(bad)?;
 
-        Ok(())
-    }
-    pub fn global() -> &'static InitConfig {
-        CONFIG.get().expect("Config not initialized")
+    let source = Source::new(&content, &extension(path));
+    let problems: Vec<String> = validate(&config)
+        .iter()
+        .map(|p| p.describe(&source))
+        .collect();
+    if !problems.is_empty() {
+        return Err(bad(anyhow!(problems.join("\n"))));
     }
+    Ok(config)
 }
 
-pub async fn read_config(file_path: &str) -> Result<HashMap<String, String>> {
-    let config = InitConfig::global();
+/// The format follows the extension, anything else is JSON with `//` and `#`
+/// comments. Errors carry the line and column.
+pub fn parse(content: &str, path: &Path) -> Result<InitConfig> {
+    let config = match extension(path).as_str() {
+        "toml" => toml::from_str(content)?,
+        "yaml" | "yml" => serde_yaml::from_str(content)?,
+        _ => serde_json::from_str(&strip_comments(content))?,
+    };
+    Ok(config)
+}
 
-    let config_full_name = &config.mapping_file;
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
 
-    let mut config = HashMap::new();
+/// Blank out `//` and `#` comments outside of strings, keeping the lines where they are.
+fn strip_comments(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut comment = false;
+    let mut chars = content.chars().peekable();
 
-    let file = File::open(config_full_name);
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            comment = false;
+            in_string = false;
+            result.push(c);
+            continue;
+        }
+        if comment {
+            continue;
+        }
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '#' || (c == '/' && chars.peek() == Some(&'/')) {
+            comment = true;
+            continue;
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// `AZMON_DB_FULL_URL=...`, `AZMON_RETRY__MAX_ATTEMPTS=6`: the name below the
+/// prefix in lower case, `__` goes one level down and numbers index lists.
+/// Values are JSON when they parse as such and fit, else strings. Names the
+/// config has no value for are refused. `AZKABAN_DB_URL` and a comma separated
+/// `FEISHU_WEBHOOK` still work.
+pub fn apply_env(
+    config: InitConfig,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<InitConfig> {
+    let mut overrides: Vec<(Vec<String>, String)> = vec![];
+    for (name, value) in vars {
+        let path = match name.as_str() {
+            "AZKABAN_DB_URL" => vec!["db_full_url".to_string()],
+            "FEISHU_WEBHOOK" => {
+                let urls: Vec<&str> = value.split(',').map(str::trim).collect();
+                overrides.insert(0, (vec!["feishu_url".to_string()], json!(urls).to_string()));
+                continue;
+            }
+            CONFIG_ENV => continue,
+            _ => match name.strip_prefix(ENV_PREFIX) {
+                Some(rest) => rest.split("__").map(str::to_lowercase).collect(),
+                None => continue,
+            },
+        };
+        // the legacy names first, `AZMON_*` wins
+        if name.starts_with(ENV_PREFIX) {
+            overrides.push((path, value));
+        } else {
+            overrides.insert(0, (path, value));
+        }
+    }
+    if overrides.is_empty() {
+        return Ok(config);
+    }
 
-    let reader = BufReader::new(file?);
+    let mut config = config;
+    for (path, value) in overrides {
+        let name = path.join(".");
+        let mut root = serde_json::to_value(&config)?;
+        let current = slot(&mut root, &path)
+            .ok_or_else(|| anyhow!("No config value {} to override", name))?
+            .clone();
+        let as_string = Value::String(value.clone());
+        // a number for a string that isn't set, like a numeric password, is tried as a string too
+        let candidates = match (current, serde_json::from_str::<Value>(&value)) {
+            (Value::String(_), _) | (_, Err(_)) => vec![as_string],
+            (_, Ok(v)) => vec![v, as_string],
+        };
 
-    for line in reader.lines() {
-        let line = line?;
-        let line = line.trim().to_string();
-        if line.is_empty() {
-            continue;
+        let mut result = Err(anyhow!("No value"));
+        for candidate in candidates {
+            if let Some(slot) = slot(&mut root, &path) {
+                *slot = candidate;
+            }
+            result = serde_json::from_value(root.clone()).map_err(anyhow::Error::from);
+            if result.is_ok() {
+                break;
+            }
         }
+        config = result.map_err(|e| anyhow!("Bad environment override {}: {}", name, e))?;
 
-        let parts: Vec<&str> = line.splitn(4, ",").collect();
-
-        if parts.len() == 2 {
-            let key = parts[0].to_string();
-            let value = parts[1].to_string();
-            config.insert(key, value);
-        } else if parts.len() == 3 {
-            let key1 = parts[0].to_string();
-            let key2 = parts[1].to_string();
-            let value = parts[2].to_string();
-            config.insert(key1, value.clone());
-            config.insert(key2, value);
-        } else if parts.len() == 4 {
-            let key1 = parts[0].to_string();
-            let key2 = parts[1].to_string();
-            let key3 = parts[2].to_string();
-            let value = parts[3].to_string();
-
-            config.insert(key1, value.clone());
-            config.insert(key2, value.clone());
-            config.insert(key3, value);
+        // serde drops what the config has no field for
+        let pointer: String = path
+            .iter()
+            .map(|k| format!("/{}", k.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        if serde_json::to_value(&config)?.pointer(&pointer).is_none() {
+            return Err(anyhow!("No config value {} to override", name));
         }
+        println!("Config {} set from the environment", name);
     }
+
     Ok(config)
 }
 
+/// The value at `path`, the last key is added to a map or an unset struct.
+fn slot<'a>(root: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    path.iter().try_fold(root, |v, key| match v {
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get_mut(i)),
+        Value::Object(map) => Some(map.entry(key.clone()).or_insert(Value::Null)),
+        Value::Null => {
+            *v = json!({});
+            v.as_object_mut()
+                .map(|map| map.entry(key.clone()).or_insert(Value::Null))
+        }
+        _ => None,
+    })
+}
+
+impl InitConfig {
+    pub fn do_parse(path: Option<&Path>) -> Result<()> {
+        let config = init(path)?;
+
+        CONFIG
+            .set(config)
+            .map_err(|_| anyhow!("Config already initialized"))?;
+
+        Ok(())
+    }
+    pub fn global() -> &'static InitConfig {
+        CONFIG.get().expect("Config not initialized")
+    }
+}
+
+/// The owner directory, see `Directory::name_mapping` for owner -> feishu id.
+/// Entries with problems are left out with a warning.
+pub async fn read_config(file_path: &str) -> Result<Directory> {
+    let (directory, problems) = directory::load(Path::new(file_path)).await?;
+    for p in problems {
+        println!("Warning: owner directory {}: {}", file_path, p);
+    }
+    Ok(directory)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::{init, read_config};
-    
+    use crate::config::{apply_env, init, load, parse, read_config};
+    use std::path::Path;
+
     use anyhow::Result;
 
     #[tokio::test]
     async fn test_init_config() -> Result<()> {
-        let result = init()?;
+        let result = init(None)?;
 
         Ok(())
     }
@@ -124,8 +273,124 @@ mod tests {
     async fn test_read_config() -> Result<()> {
         let file_path = "/Users/heise/source/config/taskr.csv";
         let config = read_config(file_path).await?;
-        let value = config.get("9527").cloned().unwrap_or("NOT_FOUND".into());
+        let value = config
+            .name_mapping()
+            .get("9527")
+            .cloned()
+            .unwrap_or("NOT_FOUND".into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_formats() -> Result<()> {
+        let json = r#"{
+            // comment lines and trailing comments
+            "feishu_url": ["https://hook/a#b"], # the # in the url stays
+            "target_cron_dir": ["/cron"],
+            "mapping_file": "owners.toml",
+            "db_full_url": "mysql://az",
+            "retry": {"max_attempts": 6}
+        }"#;
+        let toml = r#"
+feishu_url = ["https://hook/a#b"]  # the # in the url stays
+target_cron_dir = ["/cron"]
+mapping_file = "owners.toml"
+db_full_url = "mysql://az"
+
+[retry]
+max_attempts = 6
+"#;
+        let yaml = r#"
+feishu_url: ["https://hook/a#b"]
+target_cron_dir: [/cron]
+mapping_file: owners.toml
+db_full_url: mysql://az
+retry:
+  max_attempts: 6
+"#;
+        let files = [
+            (json, "monitor.config"),
+            (toml, "monitor.toml"),
+            (yaml, "monitor.yml"),
+        ];
+        for (content, name) in files {
+            let config = parse(content, Path::new(name))?;
+            assert_eq!(config.feishu_url[0].url(), "https://hook/a#b", "{}", name);
+            assert_eq!(config.retry.max_attempts, 6, "{}", name);
+        }
+
+        let err = parse("db_full_url = 1\n", Path::new("monitor.toml")).unwrap_err();
+        assert!(err.to_string().contains("line 1"), "{}", err);
+        let json = "{\n\"feishu_url\": [],\n\"target_cron_dir\": 1\n}";
+        let err = parse(json, Path::new("monitor.json")).unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_env() -> Result<()> {
+        let json = r#"{"feishu_url": [], "target_cron_dir": [], "mapping_file": "",
+            "db_full_url": "mysql://file", "azkaban_api": {"username": "az", "password": "x"}}"#;
+        let config = parse(json, Path::new("monitor.json"))?;
+        let vars = [
+            ("AZKABAN_DB_URL", "mysql://legacy"),
+            ("AZMON_DB_FULL_URL", "mysql://env"),
+            ("FEISHU_WEBHOOK", "https://hook/a, https://hook/b"),
+            ("AZMON_RETRY__MAX_ATTEMPTS", "6"),
+            ("AZMON_AZKABAN_API__PASSWORD", "1234"),
+            ("AZMON_AZKABAN_URL", "https://az"),
+            ("PATH", "/usr/bin"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let config = apply_env(config, vars)?;
+
+        assert_eq!(config.db_full_url, "mysql://env");
+        assert_eq!(config.feishu_url.len(), 2);
+        assert_eq!(config.retry.max_attempts, 6);
+        // a string stays a string
+        assert_eq!(config.azkaban_api.unwrap().password, "1234");
+        assert_eq!(config.azkaban_url.as_deref(), Some("https://az"));
+
+        // a number where no string was set yet
+        let config = parse(json, Path::new("monitor.json"))?;
+        let config = apply_env(
+            config,
+            [("AZMON_AZKABAN_URL".to_string(), "8443".to_string())],
+        )?;
+        assert_eq!(config.azkaban_url.as_deref(), Some("8443"));
+
+        for (name, value) in [
+            ("AZMON_RETRY__MAX_ATTEMPTS", "many"),
+            ("AZMON_RETRY__MAX_ATTEMPS", "6"),
+            ("AZMON_NO_SUCH_THING", "x"),
+            ("AZMON_DB_FULL_URL__HOST", "x"),
+        ] {
+            let config = parse(json, Path::new("monitor.json"))?;
+            let bad = apply_env(config, [(name.to_string(), value.to_string())]);
+            assert!(bad.is_err(), "{}", name);
+        }
+        Ok(())
+    }
 
+    #[test]
+    fn test_load_validates() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("monitor-{}.yaml", rand::random::<u32>()));
+        let yaml = "feishu_url: []
+target_cron_dir: []
+mapping_file: ''
+db_full_url: mysql://az
+retry:
+  max_delay: soon
+";
+        std::fs::write(&path, yaml)?;
+        let err = load(&path, []).unwrap_err().to_string();
+        std::fs::remove_file(&path)?;
+        assert!(
+            err.contains("line 6: retry.max_delay: Bad duration 'soon'"),
+            "{}",
+            err
+        );
         Ok(())
     }
 }
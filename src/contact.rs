@@ -0,0 +1,321 @@
+//! feishu open_ids looked up by email through the contact api, cached on disk
+
+use crate::bean::InitConfig;
+use crate::utli::parse_duration;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+/// batch_get_id takes at most this many emails per request
+const BATCH: usize = 50;
+
+/// Codes of an invalid or expired tenant_access_token
+const TOKEN_INVALID: [i64; 2] = [99991661, 99991663];
+
+pub struct ContactClient {
+    client: Client,
+    url: String,
+    app_id: String,
+    app_secret: String,
+    /// tenant_access_token and when it stops being valid
+    token: Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+impl ContactClient {
+    pub fn new(url: &str, app_id: &str, app_secret: &str) -> Self {
+        ContactClient {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            app_id: app_id.to_string(),
+            app_secret: app_secret.to_string(),
+            token: Mutex::new(None),
+        }
+    }
+
+    async fn token(&self, refresh: bool) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some((t, until)) = token.as_ref() {
+            if !refresh && Utc::now() < *until {
+                return Ok(t.clone());
+            }
+        }
+
+        let res: Value = self
+            .client
+            .post(format!(
+                "{}/open-apis/auth/v3/tenant_access_token/internal",
+                self.url
+            ))
+            .json(&json!({"app_id": self.app_id, "app_secret": self.app_secret}))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let t = match (res["code"].as_i64(), res["tenant_access_token"].as_str()) {
+            (Some(0), Some(t)) => t.to_string(),
+            _ => return Err(anyhow!("Feishu token request failed: {}", res)),
+        };
+        // a minute early, not to use it at the edge
+        let expire = res["expire"].as_i64().unwrap_or(7200) - 60;
+        *token = Some((t.clone(), Utc::now() + Duration::seconds(expire)));
+        Ok(t)
+    }
+
+    /// email -> open_id for the emails Feishu knows.
+    pub async fn batch_get_id(&self, emails: &[String]) -> Result<HashMap<String, String>> {
+        let mut result = HashMap::new();
+
+        for chunk in emails.chunks(BATCH) {
+            let mut refresh = false;
+            let res = loop {
+                let token = self.token(refresh).await?;
+                let res: Value = self
+                    .client
+                    .post(format!(
+                        "{}/open-apis/contact/v3/users/batch_get_id",
+                        self.url
+                    ))
+                    .query(&[("user_id_type", "open_id")])
+                    .bearer_auth(token)
+                    .json(&json!({"emails": chunk}))
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                let code = res["code"].as_i64().unwrap_or(-1);
+                if TOKEN_INVALID.contains(&code) && !refresh {
+                    refresh = true;
+                    continue;
+                }
+                if code != 0 {
+                    return Err(anyhow!("Feishu batch_get_id failed: {}", res));
+                }
+                break res;
+            };
+
+            for user in res["data"]["user_list"].as_array().into_iter().flatten() {
+                if let (Some(email), Some(id)) = (user["email"].as_str(), user["user_id"].as_str())
+                {
+                    result.insert(email.to_string(), id.to_string());
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cached {
+    /// `None` when Feishu has nobody with this email
+    pub open_id: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ContactCache {
+    #[serde(default)]
+    pub emails: HashMap<String, Cached>,
+}
+
+impl ContactCache {
+    fn fresh(&self, email: &str, ttl: Duration, now: DateTime<Utc>) -> Option<&Cached> {
+        self.emails.get(email).filter(|c| c.at + ttl > now)
+    }
+}
+
+pub async fn load(path: &Path) -> Result<ContactCache> {
+    if !path.exists() {
+        return Ok(ContactCache::default());
+    }
+    let content = fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub async fn save(path: &Path, cache: &ContactCache) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(cache)?).await?;
+    Ok(())
+}
+
+/// email -> open_id, asking Feishu only for emails not looked up within the ttl.
+/// Empty when no contact app is configured.
+pub async fn resolve(
+    config: &InitConfig,
+    emails: &[String],
+    now: DateTime<Utc>,
+) -> Result<HashMap<String, String>> {
+    let Some(contact) = &config.contact else {
+        return Ok(HashMap::new());
+    };
+    let ttl = Duration::from_std(parse_duration(&contact.ttl)?)?;
+    let path = Path::new(&contact.cache);
+    let mut cache = load(path).await?;
+
+    let mut missing: Vec<String> = emails
+        .iter()
+        .filter(|e| cache.fresh(e, ttl, now).is_none())
+        .cloned()
+        .collect();
+    missing.sort();
+    missing.dedup();
+
+    if !missing.is_empty() {
+        let client = ContactClient::new(&contact.url, &contact.app_id, &contact.app_secret);
+        let found = client.batch_get_id(&missing).await?;
+        println!(
+            "Looked up {} emails on Feishu, found {}",
+            missing.len(),
+            found.len()
+        );
+        for email in missing {
+            let open_id = found.get(&email).cloned();
+            cache.emails.insert(email, Cached { open_id, at: now });
+        }
+        save(path, &cache).await?;
+    }
+
+    Ok(emails
+        .iter()
+        .filter_map(|e| {
+            let id = cache.emails.get(e)?.open_id.clone()?;
+            Some((e.clone(), id))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::InitConfig;
+    use crate::contact::{resolve, ContactClient};
+    use chrono::{Duration, Utc};
+    use hyper::body::to_bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::{json, Value};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Mock {
+        tokens: usize,
+        lookups: Vec<Vec<String>>,
+    }
+
+    async fn mock_server() -> (String, Arc<Mutex<Mock>>) {
+        let mock = Arc::new(Mutex::new(Mock::default()));
+        let shared = mock.clone();
+
+        let make = make_service_fn(move |_| {
+            let mock = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let mock = mock.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let auth = req
+                            .headers()
+                            .get("authorization")
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string();
+                        let body = to_bytes(req.into_body()).await.unwrap_or_default();
+                        let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+
+                        let mut mock = mock.lock().unwrap();
+                        let res = match path.as_str() {
+                            "/open-apis/auth/v3/tenant_access_token/internal" => {
+                                assert_eq!(body["app_secret"], "secret");
+                                mock.tokens += 1;
+                                json!({"code": 0, "tenant_access_token": format!("t{}", mock.tokens), "expire": 7200})
+                            }
+                            "/open-apis/contact/v3/users/batch_get_id"
+                                if auth != format!("Bearer t{}", mock.tokens) =>
+                            {
+                                json!({"code": 99991663, "msg": "invalid access token"})
+                            }
+                            "/open-apis/contact/v3/users/batch_get_id" => {
+                                let emails: Vec<String> =
+                                    serde_json::from_value(body["emails"].clone()).unwrap();
+                                mock.lookups.push(emails.clone());
+                                let users: Vec<Value> = emails
+                                    .iter()
+                                    .map(|e| match e.as_str() {
+                                        "zs@corp.com" => json!({"email": e, "user_id": "ou_zs"}),
+                                        _ => json!({"email": e}),
+                                    })
+                                    .collect();
+                                json!({"code": 0, "msg": "success", "data": {"user_list": users}})
+                            }
+                            _ => json!({"code": 404}),
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(res.to_string())))
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, mock)
+    }
+
+    #[tokio::test]
+    async fn test_batch_get_id() -> anyhow::Result<()> {
+        let (url, mock) = mock_server().await;
+        let client = ContactClient::new(&url, "cli_a", "secret");
+
+        let found = client
+            .batch_get_id(&["zs@corp.com".to_string(), "gone@corp.com".to_string()])
+            .await?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found["zs@corp.com"], "ou_zs");
+
+        // the token is revoked on the server, the client gets a new one
+        mock.lock().unwrap().tokens += 1;
+        client.batch_get_id(&["zs@corp.com".to_string()]).await?;
+        assert_eq!(mock.lock().unwrap().tokens, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_cached() -> anyhow::Result<()> {
+        let (url, mock) = mock_server().await;
+        let cache = std::env::temp_dir().join(format!("contacts-{}.json", rand::random::<u32>()));
+        let config: InitConfig = serde_json::from_value(json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "contact": {
+                "url": url,
+                "app_id": "cli_a",
+                "app_secret": "secret",
+                "cache": cache.to_string_lossy(),
+                "ttl": "1d"
+            }
+        }))?;
+        let emails = vec!["zs@corp.com".to_string(), "gone@corp.com".to_string()];
+        let now = Utc::now();
+
+        let ids = resolve(&config, &emails, now).await?;
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids["zs@corp.com"], "ou_zs");
+
+        // found or not, both are cached until the ttl runs out
+        resolve(&config, &emails, now + Duration::hours(1)).await?;
+        assert_eq!(mock.lock().unwrap().lookups.len(), 1);
+        resolve(&config, &emails, now + Duration::days(2)).await?;
+        assert_eq!(mock.lock().unwrap().lookups.len(), 2);
+
+        std::fs::remove_file(cache)?;
+        Ok(())
+    }
+}
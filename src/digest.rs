@@ -0,0 +1,284 @@
+//! hold non-critical alerts between runs and send them as one digest card
+
+use crate::bean::{Digest, FeishuUrl, InitConfig, Severity, Task};
+use crate::notice::render_digest;
+use crate::route::Delivery;
+use crate::utli::parse_duration;
+use anyhow::Result;
+use chrono::{DateTime, Days, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs;
+
+/// Alerts held for one user on one webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pending {
+    pub url: String,
+    pub user_id: String,
+    pub first_at: DateTime<Utc>,
+    pub tasks: Vec<Task>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DigestStore {
+    pub pending: Vec<Pending>,
+}
+
+pub async fn load(path: &Path) -> Result<DigestStore> {
+    if !path.exists() {
+        return Ok(DigestStore::default());
+    }
+    let content = fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub async fn save(path: &Path, store: &DigestStore) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(store)?).await?;
+    Ok(())
+}
+
+/// Split a delivery into what has to go out now and what can wait for the digest.
+pub fn partition(digest: &Digest, delivery: Delivery) -> (Delivery, Vec<Task>) {
+    let mut now = Delivery {
+        webhook: delivery.webhook,
+        user_id: delivery.user_id,
+        projects: Default::default(),
+    };
+    let mut later = vec![];
+
+    for (project, flows) in delivery.projects {
+        for (flow, tasks) in flows {
+            for t in tasks {
+                if t.severity == Severity::Critical
+                    || digest.immediate.iter().any(|m| m.matches(&t))
+                {
+                    now.projects
+                        .entry(project.clone())
+                        .or_default()
+                        .entry(flow.clone())
+                        .or_default()
+                        .push(t);
+                } else {
+                    later.push(t);
+                }
+            }
+        }
+    }
+
+    (now, later)
+}
+
+fn same_task(a: &Task, b: &Task) -> bool {
+    a.project_name == b.project_name
+        && a.flow_id == b.flow_id
+        && a.job_id == b.job_id
+        && a.exec_id == b.exec_id
+}
+
+pub fn hold(store: &mut DigestStore, webhook: &FeishuUrl, user_id: &str, tasks: Vec<Task>) {
+    if tasks.is_empty() {
+        return;
+    }
+
+    let i = match store
+        .pending
+        .iter()
+        .position(|p| p.url == webhook.url() && p.user_id == user_id)
+    {
+        Some(i) => i,
+        None => {
+            store.pending.push(Pending {
+                url: webhook.url().to_string(),
+                user_id: user_id.to_string(),
+                first_at: Utc::now(),
+                tasks: vec![],
+            });
+            store.pending.len() - 1
+        }
+    };
+
+    let held = &mut store.pending[i].tasks;
+    for t in tasks {
+        match held.iter_mut().find(|h| same_task(h, &t)) {
+            Some(h) => *h = t,
+            None => held.push(t),
+        }
+    }
+}
+
+/// Latest occurrence of one of the `at` times that is not after `now`.
+fn last_scheduled<Tz: TimeZone>(at: &[String], now: &DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let tz = now.timezone();
+    let today = now.date_naive();
+
+    at.iter()
+        .filter_map(|s| NaiveTime::parse_from_str(s.trim(), "%H:%M").ok())
+        .flat_map(|t| {
+            let yesterday = today.checked_sub_days(Days::new(1)).map(|d| d.and_time(t));
+            [Some(today.and_time(t)), yesterday]
+        })
+        .flatten()
+        .filter_map(|ndt| tz.from_local_datetime(&ndt).earliest())
+        .map(|dt| dt.with_timezone(&Utc))
+        .filter(|dt| *dt <= now.with_timezone(&Utc))
+        .max()
+}
+
+pub fn due<Tz: TimeZone>(digest: &Digest, pending: &Pending, now: &DateTime<Tz>) -> bool {
+    let now_utc = now.with_timezone(&Utc);
+
+    if let Some(window) = digest
+        .window
+        .as_deref()
+        .and_then(|w| parse_duration(w).ok())
+    {
+        if (now_utc - pending.first_at).to_std().unwrap_or_default() >= window {
+            return true;
+        }
+    }
+
+    last_scheduled(&digest.at, now).is_some_and(|at| pending.first_at < at)
+}
+
+/// Take the due digests out of the store, rendered and ready to deliver.
+pub async fn flush<Tz: TimeZone>(
+    config: &InitConfig,
+    store: &mut DigestStore,
+    now: &DateTime<Tz>,
+) -> Vec<(Pending, FeishuUrl, Value)> {
+    let (ready, waiting): (Vec<Pending>, Vec<Pending>) = std::mem::take(&mut store.pending)
+        .into_iter()
+        .partition(|p| due(&config.digest, p, now));
+    store.pending = waiting;
+
+    let mut cards = vec![];
+    for p in ready {
+        let webhook = config
+            .webhook_by_url(&p.url)
+            .cloned()
+            .unwrap_or_else(|| FeishuUrl::Plain(p.url.clone()));
+        let card = render_digest(&p.user_id, &p.tasks, p.first_at).await;
+        cards.push((p, webhook, card));
+    }
+    cards
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{Digest, FeishuUrl, Matcher, Severity, Task};
+    use crate::digest::{due, hold, partition, DigestStore};
+    use crate::route::Delivery;
+    use chrono::{Duration, FixedOffset, TimeZone, Utc};
+    use std::collections::HashMap;
+
+    fn digest() -> Digest {
+        Digest {
+            enabled: true,
+            window: Some("1h".to_string()),
+            at: vec!["08:30".to_string()],
+            immediate: vec![Matcher {
+                project: Some("prod_*".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_partition_and_hold() {
+        let mut projects = HashMap::new();
+        for p in ["prod_bill", "test_bill", "ads_bill"] {
+            let mut t = Task::demo(p, "job", "ou_a");
+            if p == "ads_bill" {
+                t.severity = Severity::Critical;
+            }
+            projects
+                .entry(p.to_string())
+                .or_insert_with(HashMap::new)
+                .insert("daily".to_string(), vec![t]);
+        }
+        let delivery = Delivery {
+            webhook: FeishuUrl::Plain("https://hook".to_string()),
+            user_id: "ou_a".to_string(),
+            projects,
+        };
+
+        let (now, later) = partition(&digest(), delivery);
+        assert!(now.projects.contains_key("prod_bill"));
+        // critical never waits
+        assert!(now.projects.contains_key("ads_bill"));
+        assert!(!now.projects.contains_key("test_bill"));
+        assert_eq!(later.len(), 1);
+
+        let mut store = DigestStore::default();
+        let webhook = now.webhook.clone();
+        hold(&mut store, &webhook, "ou_a", later.clone());
+        hold(&mut store, &webhook, "ou_a", later);
+        hold(
+            &mut store,
+            &webhook,
+            "ou_b",
+            vec![Task::demo("x", "y", "ou_b")],
+        );
+
+        assert_eq!(store.pending.len(), 2);
+        assert_eq!(store.pending[0].tasks.len(), 1);
+    }
+
+    #[test]
+    fn test_due() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let mut store = DigestStore::default();
+        hold(
+            &mut store,
+            &FeishuUrl::Plain("https://hook".to_string()),
+            "ou_a",
+            vec![Task::demo("x", "y", "ou_a")],
+        );
+        let pending = &mut store.pending[0];
+
+        // held at 07:00 local, window 1h
+        pending.first_at = tz
+            .with_ymd_and_hms(2025, 7, 30, 7, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut d = digest();
+        assert!(!due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 30, 7, 30, 0).unwrap()
+        ));
+        assert!(due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 30, 8, 0, 0).unwrap()
+        ));
+
+        // only the schedule
+        d.window = None;
+        assert!(!due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 30, 8, 29, 0).unwrap()
+        ));
+        assert!(due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 30, 8, 30, 0).unwrap()
+        ));
+
+        // held after today's 08:30, waits for tomorrow
+        pending.first_at += Duration::hours(3);
+        assert!(!due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 30, 23, 0, 0).unwrap()
+        ));
+        assert!(due(
+            &d,
+            pending,
+            &tz.with_ymd_and_hms(2025, 7, 31, 9, 0, 0).unwrap()
+        ));
+    }
+}
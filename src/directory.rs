@@ -0,0 +1,291 @@
+//! the owner directory: who people are and how to reach them
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default, PartialEq)]
+pub struct Person {
+    pub name: String,
+    /// git author names and emails that belong to this person
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// feishu open_id
+    #[serde(default)]
+    pub feishu: Option<String>,
+    #[serde(default)]
+    pub dingtalk: Option<String>,
+    #[serde(default)]
+    pub wecom: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub team: Option<String>,
+    /// name of another person in the directory
+    #[serde(default)]
+    pub manager: Option<String>,
+}
+
+impl Person {
+    /// Everything a job owner can be written as.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.name)
+            .chain(self.aliases.iter())
+            .chain(self.email.iter())
+    }
+}
+
+#[derive(Deserialize, Clone, Serialize, Debug, Default)]
+pub struct Directory {
+    #[serde(default)]
+    pub people: Vec<Person>,
+}
+
+impl Directory {
+    /// Leave out whoever clashes with somebody listed before them, returns
+    /// what is left and the problems, empty when the directory is fine.
+    pub fn validate(self) -> (Directory, Vec<String>) {
+        let mut problems = vec![];
+        let mut people: Vec<Person> = vec![];
+        let mut owners: HashMap<String, String> = HashMap::new();
+        let mut ids: HashMap<String, String> = HashMap::new();
+
+        for p in self.people {
+            if p.name.trim().is_empty() {
+                problems.push("A person without a name, skipped".to_string());
+                continue;
+            }
+            if people.iter().any(|e| e.name == p.name) {
+                problems.push(format!("'{}' is listed twice, skipped", p.name));
+                continue;
+            }
+            let mut clashes: Vec<String> = p
+                .keys()
+                .filter_map(|key| {
+                    owners.get(key.as_str()).map(|other| {
+                        format!(
+                            "'{}' belongs to both '{}' and '{}', '{}' skipped",
+                            key, other, p.name, p.name
+                        )
+                    })
+                })
+                .collect();
+            if let Some(other) = p.feishu.as_ref().and_then(|id| ids.get(id)) {
+                clashes.push(format!(
+                    "Feishu id {} belongs to both '{}' and '{}', '{}' skipped",
+                    p.feishu.as_deref().unwrap_or_default(),
+                    other,
+                    p.name,
+                    p.name
+                ));
+            }
+            if !clashes.is_empty() {
+                problems.extend(clashes);
+                continue;
+            }
+
+            for key in p.keys() {
+                owners.insert(key.clone(), p.name.clone());
+            }
+            if let Some(id) = &p.feishu {
+                ids.insert(id.clone(), p.name.clone());
+            }
+            people.push(p);
+        }
+
+        for p in &people {
+            if let Some(m) = &p.manager {
+                if !people.iter().any(|e| &e.name == m) {
+                    problems.push(format!(
+                        "Manager '{}' of '{}' is not in the directory",
+                        m, p.name
+                    ));
+                }
+            }
+        }
+
+        (Directory { people }, problems)
+    }
+
+    /// Feishu id of whoever is known by `key`.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.people
+            .iter()
+            .find(|p| p.keys().any(|k| k == key))
+            .and_then(|p| p.feishu.as_ref())
+    }
+
+    /// Team of the person with this feishu id.
+    pub fn team_of(&self, feishu: &str) -> Option<&str> {
+        self.people
+            .iter()
+            .find(|p| p.feishu.as_deref() == Some(feishu))
+            .and_then(|p| p.team.as_deref())
+    }
+
+    /// name, alias or email -> feishu id, for everybody who has one
+    pub fn name_mapping(&self) -> HashMap<String, String> {
+        let mut mapping = HashMap::new();
+        for p in &self.people {
+            if let Some(id) = &p.feishu {
+                for key in p.keys() {
+                    mapping.insert(key.clone(), id.clone());
+                }
+            }
+        }
+        mapping
+    }
+}
+
+/// Legacy mapping file: `alias,...,feishu_id` per line, up to three aliases.
+/// Bad lines are left out and returned as problems.
+pub fn parse_csv(content: &str) -> (Directory, Vec<String>) {
+    let mut people = vec![];
+    let mut problems = vec![];
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        if parts.len() < 2 || parts.len() > 4 || parts.iter().any(|p| p.is_empty()) {
+            problems.push(format!(
+                "line {}: expected 2 to 4 columns, got '{}', skipped",
+                i + 1,
+                line
+            ));
+            continue;
+        }
+        let id = parts[parts.len() - 1];
+        people.push(Person {
+            name: parts[0].to_string(),
+            aliases: parts[1..parts.len() - 1]
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            feishu: Some(id.to_string()),
+            ..Default::default()
+        });
+    }
+
+    (Directory { people }, problems)
+}
+
+/// The format follows the extension, anything else is the legacy csv. Fails
+/// only when the file can't be parsed at all, bad entries are left out and
+/// returned as problems.
+pub fn parse(content: &str, path: &Path) -> Result<(Directory, Vec<String>)> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let (directory, mut problems) = match ext.as_str() {
+        "toml" => (toml::from_str(content)?, vec![]),
+        "yaml" | "yml" => (serde_yaml::from_str(content)?, vec![]),
+        "json" => (serde_json::from_str(content)?, vec![]),
+        _ => parse_csv(content),
+    };
+
+    let (directory, more) = Directory::validate(directory);
+    problems.extend(more);
+    Ok((directory, problems))
+}
+
+pub async fn load(path: &Path) -> Result<(Directory, Vec<String>)> {
+    let content = fs::read_to_string(path).await?;
+    parse(&content, path).map_err(|e| anyhow!("Bad owner directory {}:\n{}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::directory::{parse, parse_csv};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_formats() {
+        let toml = r#"
+[[people]]
+name = "zhangsan"
+aliases = ["Zhang San", "zs@corp.com"]
+feishu = "ou_zs"
+team = "warehouse"
+manager = "lisi"
+
+[[people]]
+name = "lisi"
+feishu = "ou_ls"
+email = "lisi@corp.com"
+"#;
+        let yaml = r#"
+people:
+  - name: zhangsan
+    aliases: [Zhang San, zs@corp.com]
+    feishu: ou_zs
+    team: warehouse
+    manager: lisi
+  - name: lisi
+    feishu: ou_ls
+    email: lisi@corp.com
+"#;
+        let json = r#"{"people": [
+            {"name": "zhangsan", "aliases": ["Zhang San", "zs@corp.com"], "feishu": "ou_zs",
+             "team": "warehouse", "manager": "lisi"},
+            {"name": "lisi", "feishu": "ou_ls", "email": "lisi@corp.com"}
+        ]}"#;
+
+        let (a, problems) = parse(toml, Path::new("owners.toml")).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+        let (b, _) = parse(yaml, Path::new("owners.yml")).unwrap();
+        let (c, _) = parse(json, Path::new("owners.json")).unwrap();
+        assert_eq!(a.people, b.people);
+        assert_eq!(a.people, c.people);
+
+        let mapping = a.name_mapping();
+        assert_eq!(mapping["Zhang San"], "ou_zs");
+        assert_eq!(mapping["lisi@corp.com"], "ou_ls");
+        assert_eq!(a.get("zs@corp.com").map(String::as_str), Some("ou_zs"));
+        assert_eq!(a.team_of("ou_zs"), Some("warehouse"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let json = r#"{"people": [
+            {"name": "zhangsan", "aliases": ["zs"], "feishu": "ou_1", "manager": "boss"},
+            {"name": "zhaosi", "aliases": ["zs"], "feishu": "ou_1"},
+            {"name": "zhaosi", "feishu": "ou_2"},
+            {"name": "zhaosi"},
+            {"name": "wangwu", "feishu": "ou_1"}
+        ]}"#;
+        let (dir, problems) = parse(json, Path::new("owners.json")).unwrap();
+        let names: Vec<&str> = dir.people.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["zhangsan", "zhaosi"]);
+        let mapping = dir.name_mapping();
+        assert_eq!(mapping["zs"], "ou_1");
+        assert_eq!(mapping["zhaosi"], "ou_2");
+
+        let problems = problems.join("\n");
+        assert!(problems.contains("'zs' belongs to both 'zhangsan' and 'zhaosi'"));
+        assert!(problems.contains("Feishu id ou_1 belongs to both 'zhangsan' and 'zhaosi'"));
+        assert!(problems.contains("'zhaosi' is listed twice"));
+        assert!(problems.contains("Feishu id ou_1 belongs to both 'zhangsan' and 'wangwu'"));
+        assert!(problems.contains("Manager 'boss' of 'zhangsan'"));
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let (dir, problems) = parse_csv("zhangsan,Zhang San,ou_zs\n\nlisi,ou_ls\n");
+        assert!(problems.is_empty());
+        let mapping = dir.name_mapping();
+        assert_eq!(mapping.len(), 3);
+        assert_eq!(mapping["Zhang San"], "ou_zs");
+
+        let (dir, problems) = parse_csv("zhangsan,ou_zs\nnobody\nlisi,ou_ls\n");
+        assert_eq!(dir.people.len(), 2);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("line 2"));
+    }
+}
@@ -0,0 +1,217 @@
+//! escalation tiers for alerts nobody acked
+
+use crate::bean::{Escalation, FeishuUrl, InitConfig, Severity, Task, Tier};
+use crate::oncall;
+use crate::state::Alert;
+use crate::utli::parse_duration;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+/// A tier user `team:<name>` is the lead of that team.
+pub const TEAM_PREFIX: &str = "team:";
+
+/// Info alerts are never escalated.
+pub fn policy_for<'a>(config: &'a InitConfig, task: &Task) -> Option<&'a Escalation> {
+    if task.severity == Severity::Info {
+        return None;
+    }
+    config.escalations.iter().find(|e| e.matcher.matches(task))
+}
+
+/// Tiers that are due and not notified yet, in order.
+pub fn due_tiers(policy: &Escalation, alert: &Alert, now: DateTime<Utc>) -> Vec<usize> {
+    let Some(since) = alert.first_notified_at else {
+        return vec![];
+    };
+    if alert.acked_by.is_some() {
+        return vec![];
+    }
+
+    policy
+        .tiers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !alert.escalated.contains(i))
+        .filter(|(_, tier)| match parse_duration(&tier.after) {
+            Ok(after) => Duration::from_std(after).is_ok_and(|after| since + after <= now),
+            Err(e) => {
+                println!("Warning: Bad escalation tier: {}", e);
+                false
+            }
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Feishu id of a tier user, resolved like route assignees once a team is
+/// replaced by its lead.
+pub fn resolve_user(
+    config: &InitConfig,
+    name_mapping: &HashMap<String, String>,
+    user: &str,
+    now: DateTime<Utc>,
+) -> Result<String> {
+    let user = match user.strip_prefix(TEAM_PREFIX) {
+        Some(name) => config
+            .teams
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown team '{}'", name))?
+            .lead
+            .as_deref()
+            .ok_or_else(|| anyhow!("Team '{}' has no lead", name))?,
+        None => user,
+    };
+    oncall::resolve(config, name_mapping, user, now)
+}
+
+/// Webhooks and the @ of one tier: its users in every chat the alert went to,
+/// its channels with @all or nobody.
+pub fn targets(
+    config: &InitConfig,
+    name_mapping: &HashMap<String, String>,
+    tier: &Tier,
+    alert: &Alert,
+    now: DateTime<Utc>,
+) -> Vec<(FeishuUrl, String)> {
+    let mut result: Vec<(FeishuUrl, String)> = vec![];
+    let mut push = |webhook: FeishuUrl, user_id: &str| {
+        if !result
+            .iter()
+            .any(|(w, u)| w.url() == webhook.url() && u == user_id)
+        {
+            result.push((webhook, user_id.to_string()));
+        }
+    };
+
+    for user in &tier.users {
+        let user = match resolve_user(config, name_mapping, user, now) {
+            Ok(id) => id,
+            Err(e) => {
+                println!("Warning: Can't resolve escalation user {}: {}", user, e);
+                continue;
+            }
+        };
+        for n in &alert.notified {
+            let webhook = config
+                .webhook_by_url(&n.url)
+                .cloned()
+                .unwrap_or_else(|| FeishuUrl::Plain(n.url.clone()));
+            push(webhook, &user);
+        }
+    }
+
+    let at = if tier.at_all { "all" } else { "" };
+    let mut channels: Vec<&FeishuUrl> = tier
+        .channels
+        .iter()
+        .filter_map(|name| {
+            let c = config.channels.get(name);
+            if c.is_none() {
+                println!("Warning: escalation refers to unknown channel '{}'", name);
+            }
+            c
+        })
+        .collect();
+    if tier.team_channel {
+        channels.extend(config.fallback.team_channels.get(&alert.task.project_name));
+    }
+    for c in channels {
+        push(c.clone(), at);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{Escalation, InitConfig, Severity, Task, Tier};
+    use crate::escalate::{due_tiers, policy_for, targets};
+    use crate::state::{alert_key, AlertState};
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_escalation() {
+        let policy = Escalation {
+            tiers: vec![
+                Tier {
+                    after: "30m".to_string(),
+                    users: vec!["ou_lead".to_string(), "Wang Wu".to_string()],
+                    ..Default::default()
+                },
+                Tier {
+                    after: "2h".to_string(),
+                    team_channel: true,
+                    at_all: true,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let task = Task::demo("warehouse", "a", "ou_a");
+        let mut state = AlertState::default();
+        state.observe(&task, now);
+        let key = alert_key(&task);
+
+        // never sent to the owner, nothing to escalate
+        assert!(due_tiers(&policy, &state.alerts[&key], now).is_empty());
+
+        state.record_notified(&task, "https://hook/owner", "ou_a");
+        let alert = state.alerts.get_mut(&key).unwrap();
+        alert.first_notified_at = Some(now - Duration::minutes(40));
+        assert_eq!(due_tiers(&policy, alert, now), vec![0]);
+        alert.escalated.push(0);
+        assert!(due_tiers(&policy, alert, now).is_empty());
+        assert_eq!(due_tiers(&policy, alert, now + Duration::hours(2)), vec![1]);
+
+        let config: InitConfig = serde_json::from_value(serde_json::json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "fallback": {"team_channels": {"warehouse": "https://hook/team"}},
+            "escalations": [{"project": "warehouse", "tiers": []}],
+            "rotations": {"dw": {"members": ["Li Si"], "start": "2025-07-28"}},
+            "teams": {"dw": {"lead": "oncall:dw"}, "ads": {}}
+        }))
+        .unwrap();
+        let mut info = task.clone();
+        assert!(policy_for(&config, &info).is_some());
+        info.severity = Severity::Info;
+        assert!(policy_for(&config, &info).is_none());
+
+        let mapping = HashMap::from([
+            ("Wang Wu".to_string(), "ou_wang".to_string()),
+            ("Li Si".to_string(), "ou_li".to_string()),
+        ]);
+        let lead = targets(&config, &mapping, &policy.tiers[0], alert, now);
+        let users: Vec<(&str, &str)> = lead.iter().map(|(w, u)| (w.url(), u.as_str())).collect();
+        assert_eq!(
+            users,
+            vec![
+                ("https://hook/owner", "ou_lead"),
+                ("https://hook/owner", "ou_wang")
+            ]
+        );
+        // a team is its lead, an unknown or leaderless one nobody
+        let tier = Tier {
+            users: vec!["team:dw".into(), "team:ads".into(), "team:x".into()],
+            ..Default::default()
+        };
+        let lead = targets(&config, &mapping, &tier, alert, now);
+        assert_eq!(lead.len(), 1);
+        assert_eq!(lead[0].1, "ou_li");
+
+        let team = targets(&config, &mapping, &policy.tiers[1], alert, now);
+        assert_eq!(
+            (team[0].0.url(), team[0].1.as_str()),
+            ("https://hook/team", "all")
+        );
+
+        // acked alerts stay where they are
+        alert.acked_by = Some("ou_a".to_string());
+        assert!(due_tiers(&policy, alert, now + Duration::hours(3)).is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! per-job failure rates over the recent runs, to tell flapping jobs apart
+
+use crate::bean::{Flapping, Task};
+use crate::utli::parse_duration;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Days, Duration, NaiveTime, TimeZone, Utc, Weekday};
+use std::collections::HashMap;
+
+/// Final attempt of one execution of a job
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub exec_id: String,
+    pub attempt: u8,
+    pub status: i32,
+    pub end_time: DateTime<Utc>,
+}
+
+impl Run {
+    pub fn passed(&self) -> bool {
+        self.status == 50
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JobRuns {
+    pub project: String,
+    pub flow: String,
+    pub job: String,
+    /// oldest first
+    pub runs: Vec<Run>,
+}
+
+/// job key -> runs
+pub type History = HashMap<String, JobRuns>;
+
+#[derive(Debug, PartialEq)]
+pub struct Rate {
+    pub window: String,
+    pub runs: usize,
+    pub failures: usize,
+}
+
+impl Rate {
+    pub fn value(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.runs as f64
+        }
+    }
+}
+
+/// The longest window, how far back the history has to go.
+pub fn lookback(config: &Flapping) -> Result<Duration> {
+    let mut longest = std::time::Duration::ZERO;
+    for w in &config.windows {
+        longest = longest.max(parse_duration(w)?);
+    }
+    Ok(Duration::from_std(longest)?)
+}
+
+pub fn rates(config: &Flapping, runs: &[Run], now: DateTime<Utc>) -> Vec<Rate> {
+    config
+        .windows
+        .iter()
+        .filter_map(|w| {
+            let since = now - Duration::from_std(parse_duration(w).ok()?).ok()?;
+            let recent: Vec<&Run> = runs.iter().filter(|r| r.end_time > since).collect();
+            Some(Rate {
+                window: w.clone(),
+                runs: recent.len(),
+                failures: recent.iter().filter(|r| !r.passed()).count(),
+            })
+        })
+        .collect()
+}
+
+/// A job that always fails is broken, not flapping.
+pub fn is_flapping(config: &Flapping, rates: &[Rate]) -> bool {
+    rates
+        .iter()
+        .any(|r| r.runs >= config.min_runs && r.failures < r.runs && r.value() >= config.threshold)
+}
+
+/// Latest `n` runs as ✅/❌, oldest on the left.
+pub fn sparkline(runs: &[Run], n: usize) -> String {
+    runs[runs.len().saturating_sub(n)..]
+        .iter()
+        .map(|r| if r.passed() { "✅" } else { "❌" })
+        .collect()
+}
+
+pub fn describe(rates: &[Rate]) -> String {
+    rates
+        .iter()
+        .map(|r| {
+            format!(
+                "{} {:.0}% ({}/{})",
+                r.window,
+                r.value() * 100.0,
+                r.failures,
+                r.runs
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+/// Card note for a flapping job, `None` otherwise.
+pub fn note(config: &Flapping, runs: &[Run], now: DateTime<Utc>) -> Option<String> {
+    let rates = rates(config, runs, now);
+    if !is_flapping(config, &rates) {
+        return None;
+    }
+    Some(format!(
+        "🔀 间歇性失败, 失败率 {}, 最近: {}",
+        describe(&rates),
+        sparkline(runs, config.history)
+    ))
+}
+
+/// Whether the weekly report at `at` (`Mon 09:30`) is due, given when it was last sent.
+pub fn report_due<Tz: TimeZone>(
+    at: &str,
+    last: Option<DateTime<Utc>>,
+    now: &DateTime<Tz>,
+) -> Result<bool> {
+    let (day, time) = at
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("Bad report_at '{}', expected like 'Mon 09:30'", at))?;
+    let day: Weekday = day
+        .parse()
+        .map_err(|_| anyhow!("Bad weekday in report_at '{}'", at))?;
+    let time = NaiveTime::parse_from_str(time.trim(), "%H:%M")?;
+
+    let today = now.date_naive();
+    let back = (7 + today.weekday().num_days_from_monday() - day.num_days_from_monday()) % 7;
+    let date = today
+        .checked_sub_days(Days::new(back as u64))
+        .ok_or_else(|| anyhow!("Date out of range"))?;
+    let mut scheduled = now
+        .timezone()
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .ok_or_else(|| anyhow!("No local time {} {}", date, time))?
+        .with_timezone(&Utc);
+    if scheduled > now.with_timezone(&Utc) {
+        scheduled -= Duration::days(7);
+    }
+
+    Ok(last.is_none_or(|l| l < scheduled))
+}
+
+impl JobRuns {
+    /// Stand-in task for the job from one of its runs, for reports.
+    pub fn task(&self, run: &Run) -> Task {
+        Task {
+            exec_id: run.exec_id.clone(),
+            project_name: self.project.clone(),
+            flow_id: self.flow.clone(),
+            job_id: self.job.clone(),
+            attempt: run.attempt,
+            status: run.status,
+            owner: String::new(),
+            input_params: String::new(),
+            output_params: String::new(),
+            start_time: run.end_time,
+            end_time: run.end_time,
+            duration: std::time::Duration::ZERO,
+            desc: String::new(),
+            log_excerpt: String::new(),
+            source_url: None,
+            unowned: None,
+            team: None,
+            instance: None,
+            severity: Default::default(),
+        }
+    }
+}
+
+/// Stand-in task for a flaky job in the weekly report, from its latest failed run.
+pub fn flaky_task(job: &JobRuns) -> Option<Task> {
+    let failed = job.runs.iter().rev().find(|r| !r.passed())?;
+    Some(job.task(failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::Flapping;
+    use crate::flapping::{
+        flaky_task, is_flapping, note, rates, report_due, sparkline, JobRuns, Run,
+    };
+    use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+
+    /// `+` passed, `-` failed, one run every 12 hours up to `now`
+    fn runs(pattern: &str, now: DateTime<Utc>) -> Vec<Run> {
+        let n = pattern.len() as i64;
+        pattern
+            .chars()
+            .enumerate()
+            .map(|(i, c)| Run {
+                exec_id: (100 + i).to_string(),
+                attempt: 0,
+                status: if c == '+' { 50 } else { 70 },
+                end_time: now - Duration::hours(12 * (n - i as i64) - 1),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flapping() {
+        let config = Flapping {
+            enabled: true,
+            windows: vec!["1d".to_string(), "7d".to_string()],
+            ..Default::default()
+        };
+        let now = Utc::now();
+
+        // 14 runs over 7 days, 4 failed
+        let flaky = runs("++-+++-++-++-+", now);
+        let r = rates(&config, &flaky, now);
+        assert_eq!((r[0].runs, r[0].failures), (2, 1));
+        assert_eq!((r[1].runs, r[1].failures), (14, 4));
+        assert!(is_flapping(&config, &r));
+        assert_eq!(sparkline(&flaky, 4), "✅✅❌✅");
+        assert!(note(&config, &flaky, now)
+            .unwrap()
+            .contains("7d 29% (4/14)"));
+
+        // always failing or always passing is not flapping
+        assert!(note(&config, &runs("------", now), now).is_none());
+        assert!(note(&config, &runs("++++++", now), now).is_none());
+        // too few runs
+        assert!(note(&config, &runs("+-+", now), now).is_none());
+
+        let job = JobRuns {
+            project: "warehouse".to_string(),
+            flow: "daily".to_string(),
+            job: "a".to_string(),
+            runs: flaky,
+        };
+        let task = flaky_task(&job).unwrap();
+        assert_eq!(task.job_id, "a");
+        assert_eq!(task.exec_id, "112");
+    }
+
+    #[test]
+    fn test_report_due() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        // Wednesday
+        let now = tz.with_ymd_and_hms(2025, 7, 30, 10, 0, 0).unwrap();
+        let monday = tz
+            .with_ymd_and_hms(2025, 7, 28, 9, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(report_due("Mon 09:30", None, &now).unwrap());
+        assert!(report_due("Mon 09:30", Some(monday - Duration::days(1)), &now).unwrap());
+        assert!(!report_due("Mon 09:30", Some(monday), &now).unwrap());
+        // this week's Wednesday 11:00 is still ahead, last week's was sent
+        let last_wed = tz
+            .with_ymd_and_hms(2025, 7, 23, 11, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(!report_due("Wed 11:00", Some(last_wed), &now).unwrap());
+        assert!(report_due("Someday 11:00", None, &now).is_err());
+    }
+}
@@ -1,11 +1,17 @@
 use crate::bean::Job;
+use crate::link::{blob_url, git_web_base};
 use anyhow::{anyhow, Result};
-use chrono::NaiveDateTime;
-use regex::Regex;
-use std::path::PathBuf;
+use std::path::Path;
 use tokio::process::Command;
 
-pub async fn blame(job: &Job) -> Result<String> {
+/// Author of the latest commit touching some lines.
+#[derive(Debug, Default, PartialEq)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+}
+
+pub async fn blame(job: &Job) -> Result<Author> {
     let start_line_no = job.start;
     let end_line_no = job.end;
     let origin_file = &job.flow_file;
@@ -24,6 +30,7 @@ pub async fn blame(job: &Job) -> Result<String> {
     let output = Command::new("git")
         .current_dir(&parent_path)
         .arg("blame")
+        .arg("--line-porcelain")
         .arg(format!("-L{},{}", start_line_no, end_line_no))
         .arg(file_name)
         .output()
@@ -36,40 +43,99 @@ pub async fn blame(job: &Job) -> Result<String> {
 
     let blame_info = String::from_utf8_lossy(&output.stdout);
 
-    let owner = extract_latest_author(blame_info.to_string().as_str()).await?;
+    let owner = latest_author(&blame_info);
 
-    println!("Blame : {blame_info} -> user : {owner}");
+    println!(
+        "Blame : {:?} {}-{} -> user : {} <{}>",
+        origin_file, start_line_no, end_line_no, owner.name, owner.email
+    );
 
     Ok(owner)
 }
 
-async fn extract_latest_author(blame_info: &str) -> Result<String> {
-    let re = Regex::new(r"\((\S+)\s+(\d{4}-\d{2}-\d{2})\s+(\d{2}:\d{2}:\d{2})").unwrap();
-    let mut latest_author = None;
-    let mut latest_dt = None;
-
-    for line in blame_info.lines() {
-        if let Some(cap) = re.captures(line) {
-            let author = cap[1].to_string();
-            let date_str = &cap[2];
-            let time_str = &cap[3];
-            let dt_str = format!("{} {}", date_str, time_str);
-            if let Ok(dt) = NaiveDateTime::parse_from_str(&dt_str, "%Y-%m-%d %H:%M:%S") {
-                if latest_dt.is_none() || dt > latest_dt.unwrap() {
-                    latest_dt = Some(dt);
-                    latest_author = Some(author);
-                }
+pub async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("git {} failed: {}", args.join(" "), err));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Web url of `file` at the checked out revision, `web_base` overrides the one
+/// derived from the `origin` remote.
+pub async fn file_blob_url(file: &Path, web_base: Option<&str>) -> Result<Option<String>> {
+    let parent_path = file
+        .parent()
+        .ok_or_else(|| anyhow!("Can't get parent dir"))?
+        .canonicalize()?;
+
+    let file_name = file
+        .file_name()
+        .ok_or_else(|| anyhow!("Can't get file name"))?
+        .to_string_lossy()
+        .to_string();
+
+    let web_base = match web_base {
+        Some(base) => Some(base.to_string()),
+        None => git_web_base(&git(&parent_path, &["remote", "get-url", "origin"]).await?),
+    };
+
+    let Some(web_base) = web_base else {
+        return Ok(None);
+    };
+
+    let rev = git(&parent_path, &["rev-parse", "HEAD"]).await?;
+    let path = git(&parent_path, &["ls-files", "--full-name", &file_name]).await?;
+
+    if path.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(blob_url(&web_base, &rev, &path)))
+}
+
+/// Picks the author with the latest `author-time` from `git blame --line-porcelain`.
+fn latest_author(porcelain: &str) -> Author {
+    let mut latest: Option<(i64, Author)> = None;
+    let mut name = String::new();
+    let mut email = String::new();
+
+    for line in porcelain.lines() {
+        if let Some(v) = line.strip_prefix("author ") {
+            name = v.to_string();
+        } else if let Some(v) = line.strip_prefix("author-mail ") {
+            email = v.trim_start_matches('<').trim_end_matches('>').to_string();
+        } else if let Some(v) = line.strip_prefix("author-time ") {
+            let Ok(time) = v.trim().parse::<i64>() else {
+                continue;
+            };
+            if latest.as_ref().is_none_or(|(t, _)| time > *t) {
+                latest = Some((
+                    time,
+                    Author {
+                        name: name.clone(),
+                        email: email.clone(),
+                    },
+                ));
             }
         }
     }
-    Ok(latest_author.unwrap_or_default())
+
+    latest.map(|(_, a)| a).unwrap_or_default()
 }
 
 #[cfg(test)]
 #[tokio::test]
 async fn test_blame() {
     let job = Job {
-        flow_file: PathBuf::from(
+        flow_file: std::path::PathBuf::from(
             "/Users/fjyulu/enterprise/playground/new/warehouse/cron/warehouse/it_digital_day.flow",
         ),
         start: 239,
@@ -78,9 +144,92 @@ async fn test_blame() {
         job: "dwd_idc_tencloud_bill_detail_pmi".to_string(),
         other: " ".to_string(),
         owner: "".to_string(),
+        owner_email: "".to_string(),
         desc: "".to_string(),
+        source_url: None,
     };
 
     let r = blame(&job).await.unwrap();
-    println!("result: {}", r);
+    println!("result: {:?}", r);
+}
+
+#[cfg(test)]
+#[test]
+fn test_latest_author() {
+    let porcelain = "\
+3f1c2a 239 239 2
+author Zhang San
+author-mail <zs@corp.com>
+author-time 1700000000
+author-tz +0800
+filename it_digital_day.flow
+\tjobs:
+9b8e7d 240 240
+author lisi
+author-mail <lisi@corp.com>
+author-time 1710000000
+author-tz +0800
+filename it_digital_day.flow
+\t  - name: a
+";
+    assert_eq!(
+        latest_author(porcelain),
+        Author {
+            name: "lisi".to_string(),
+            email: "lisi@corp.com".to_string()
+        }
+    );
+    assert_eq!(latest_author(""), Author::default());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_blame_applies_mailmap() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("blame-{}", rand::random::<u32>()));
+    std::fs::create_dir_all(&dir)?;
+
+    git(&dir, &["init", "-q"]).await?;
+    for (author, file, content) in [
+        (
+            "zhangsan <zhangsan@gmail.com>",
+            "daily.flow",
+            "jobs:\n  - name: a\n",
+        ),
+        (
+            "ci <ci@corp.com>",
+            ".mailmap",
+            "Zhang San <zs@corp.com> <zhangsan@gmail.com>\n",
+        ),
+    ] {
+        std::fs::write(dir.join(file), content)?;
+        git(&dir, &["add", file]).await?;
+        let identity = ["-c", "user.name=ci", "-c", "user.email=ci@corp.com"];
+        let commit = ["commit", "-q", "--author", author, "-m", file];
+        git(&dir, &[&identity[..], &commit[..]].concat()).await?;
+    }
+
+    let job = Job {
+        flow_file: dir.join("daily.flow"),
+        start: 1,
+        end: 2,
+        flow: "daily".to_string(),
+        job: "a".to_string(),
+        other: String::new(),
+        owner: String::new(),
+        owner_email: String::new(),
+        desc: String::new(),
+        source_url: None,
+    };
+    let author = blame(&job).await;
+    std::fs::remove_dir_all(&dir)?;
+
+    // the commit author, as the .mailmap names them
+    assert_eq!(
+        author?,
+        Author {
+            name: "Zhang San".to_string(),
+            email: "zs@corp.com".to_string()
+        }
+    );
+    Ok(())
 }
@@ -0,0 +1,142 @@
+//! one name per person: blamed authors through the alias table, `git blame`
+//! has applied the repository's `.mailmap` already
+
+use crate::bean::Job;
+use std::collections::{BTreeMap, HashMap};
+
+/// The name the owner directory knows a blamed author by, emails compare
+/// case-insensitively.
+pub fn canonical(aliases: &HashMap<String, String>, name: &str, email: &str) -> String {
+    aliases
+        .get(name)
+        .or_else(|| {
+            aliases
+                .iter()
+                .find(|(k, _)| !email.is_empty() && k.eq_ignore_ascii_case(email))
+                .map(|(_, v)| v)
+        })
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Feishu id of the owner of a job: the canonical name or the email in the
+/// directory, then the first word of the name as mapping files written for
+/// the plain `git blame` output have it (`Zhang` for `Zhang San`), then the
+/// ids looked up by email.
+pub fn owner_id<'a>(
+    aliases: &HashMap<String, String>,
+    name_mapping: &'a HashMap<String, String>,
+    looked_up: &'a HashMap<String, String>,
+    job: &Job,
+) -> Option<&'a String> {
+    let name = canonical(aliases, &job.owner, &job.owner_email);
+    name_mapping
+        .get(&name)
+        .or_else(|| name_mapping.get(&job.owner_email))
+        .or_else(|| {
+            job.owner
+                .split_whitespace()
+                .next()
+                .and_then(|first| name_mapping.get(first))
+        })
+        .or_else(|| looked_up.get(&job.owner_email))
+}
+
+/// Blamed authors of the jobs in the cron dirs nobody could be found for,
+/// `name <email>` -> job keys.
+pub fn unresolved(
+    aliases: &HashMap<String, String>,
+    name_mapping: &HashMap<String, String>,
+    looked_up: &HashMap<String, String>,
+    jobs: &HashMap<String, HashMap<String, HashMap<String, Job>>>,
+) -> BTreeMap<String, Vec<String>> {
+    let mut result: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for (project, flows) in jobs {
+        for (flow, jobs) in flows {
+            for (name, job) in jobs {
+                if job.owner.is_empty() || owner_id(aliases, name_mapping, looked_up, job).is_some()
+                {
+                    continue;
+                }
+                let identity = if job.owner_email.is_empty() {
+                    job.owner.clone()
+                } else {
+                    format!("{} <{}>", job.owner, job.owner_email)
+                };
+                result
+                    .entry(identity)
+                    .or_default()
+                    .push(format!("{}.{}.{}", project, flow, name));
+            }
+        }
+    }
+
+    for jobs in result.values_mut() {
+        jobs.sort();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::Job;
+    use crate::identity::{canonical, owner_id, unresolved};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_unresolved() {
+        let aliases = HashMap::from([
+            ("San Zhang".to_string(), "zhangsan".to_string()),
+            ("ZS@gmail.com".to_string(), "zhangsan".to_string()),
+        ]);
+        assert_eq!(canonical(&aliases, "San Zhang", ""), "zhangsan");
+        assert_eq!(canonical(&aliases, "zs", "zs@gmail.com"), "zhangsan");
+        assert_eq!(canonical(&aliases, "lisi", "lisi@corp.com"), "lisi");
+
+        let mapping = HashMap::from([
+            ("zhangsan".to_string(), "ou_zs".to_string()),
+            ("Zhao".to_string(), "ou_zhao".to_string()),
+        ]);
+        let looked_up = HashMap::from([("ww@corp.com".to_string(), "ou_ww".to_string())]);
+        let job = |owner: &str, email: &str| Job {
+            start: 1,
+            end: 2,
+            flow: "daily".to_string(),
+            job: String::new(),
+            other: String::new(),
+            flow_file: Default::default(),
+            owner: owner.to_string(),
+            owner_email: email.to_string(),
+            desc: String::new(),
+            source_url: None,
+        };
+        let jobs = HashMap::from([(
+            "warehouse".to_string(),
+            HashMap::from([(
+                "daily".to_string(),
+                HashMap::from([
+                    ("a".to_string(), job("San Zhang", "")),
+                    ("b".to_string(), job("Wang Wu", "ww@corp.com")),
+                    ("c".to_string(), job("lisi", "lisi@corp.com")),
+                    ("d".to_string(), job("lisi", "lisi@corp.com")),
+                    ("e".to_string(), job("", "")),
+                    ("f".to_string(), job("Zhao Liu", "zl@corp.com")),
+                ]),
+            )]),
+        )]);
+
+        let report = unresolved(&aliases, &mapping, &looked_up, &jobs);
+        assert_eq!(report.len(), 1);
+        assert_eq!(
+            report["lisi <lisi@corp.com>"],
+            vec!["warehouse.daily.c", "warehouse.daily.d"]
+        );
+        // a mapping file keyed by the first word of the name still works
+        let zhao = &jobs["warehouse"]["daily"]["f"];
+        assert_eq!(
+            owner_id(&aliases, &mapping, &looked_up, zhao).unwrap(),
+            "ou_zhao"
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! where the values of a config file are, by their path like `routes[2].channels[0]`
+
+use std::collections::HashMap;
+use toml_edit::{ImDocument, Item, TableLike, Value};
+
+/// A config file with the line of every key and list item in it.
+pub struct Source<'a> {
+    content: &'a str,
+    lines: HashMap<String, usize>,
+}
+
+impl<'a> Source<'a> {
+    /// `ext` picks the format like `config::parse` does, nothing is found in
+    /// content that doesn't parse.
+    pub fn new(content: &'a str, ext: &str) -> Self {
+        let mut lines = HashMap::new();
+        match ext {
+            "toml" => {
+                if let Ok(doc) = ImDocument::parse(content) {
+                    toml_item(content, doc.as_item(), "", &mut lines);
+                }
+            }
+            "yaml" | "yml" => yaml(content, &mut lines),
+            _ => {
+                let mut at = 0;
+                json(content, &mut at, "", &mut lines);
+            }
+        }
+        Source { content, lines }
+    }
+
+    /// Line of `path`, else of the closest parent that is in the file. `None`
+    /// when that line doesn't have `value`, e.g. one set from the environment.
+    pub fn line_of(&self, path: &str, value: &str) -> Option<usize> {
+        let mut path = path;
+        let n = loop {
+            if let Some(n) = self.lines.get(path) {
+                break *n;
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        };
+        let line = self.content.lines().nth(n - 1)?;
+        line.contains(value).then_some(n)
+    }
+}
+
+fn child(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    }
+}
+
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+fn toml_item(content: &str, item: &Item, path: &str, lines: &mut HashMap<String, usize>) {
+    match item {
+        Item::ArrayOfTables(tables) => {
+            for (i, t) in tables.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                if let Some(span) = t.span() {
+                    lines.insert(path.clone(), line_at(content, span.start));
+                }
+                toml_table(content, t, &path, lines);
+            }
+        }
+        Item::Table(t) => toml_table(content, t, path, lines),
+        Item::Value(v) => toml_value(content, v, path, lines),
+        Item::None => {}
+    }
+}
+
+fn toml_value(content: &str, value: &Value, path: &str, lines: &mut HashMap<String, usize>) {
+    match value {
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                if let Some(span) = v.span() {
+                    lines.insert(path.clone(), line_at(content, span.start));
+                }
+                toml_value(content, v, &path, lines);
+            }
+        }
+        Value::InlineTable(t) => toml_table(content, t, path, lines),
+        _ => {}
+    }
+}
+
+fn toml_table(
+    content: &str,
+    table: &dyn TableLike,
+    path: &str,
+    lines: &mut HashMap<String, usize>,
+) {
+    for (key, value) in table.iter() {
+        let path = child(path, key);
+        // a table's own span is its header, its key may be in a parent header
+        let span = match value {
+            Item::Table(t) => t.span(),
+            _ => None,
+        }
+        .or_else(|| table.get_key_value(key).and_then(|(k, _)| k.span()))
+        .or_else(|| value.span());
+        if let Some(span) = span {
+            lines.insert(path.clone(), line_at(content, span.start));
+        }
+        toml_item(content, value, &path, lines);
+    }
+}
+
+/// JSON with `//` and `#` comments, which are skipped like whitespace.
+fn json(
+    content: &str,
+    at: &mut usize,
+    path: &str,
+    lines: &mut HashMap<String, usize>,
+) -> Option<()> {
+    let s = content.as_bytes();
+    json_skip(s, at);
+    if !path.is_empty() {
+        lines.insert(path.to_string(), line_at(content, *at));
+    }
+    match s.get(*at)? {
+        b'{' | b'[' => {
+            let object = s[*at] == b'{';
+            *at += 1;
+            let mut i = 0;
+            loop {
+                json_skip(s, at);
+                if matches!(s.get(*at)?, b'}' | b']') {
+                    *at += 1;
+                    return Some(());
+                }
+                let path = if object {
+                    let key = json_string(s, at)?;
+                    json_skip(s, at);
+                    (s.get(*at)? == &b':').then_some(())?;
+                    *at += 1;
+                    child(path, &key)
+                } else {
+                    format!("{}[{}]", path, i)
+                };
+                json(content, at, &path, lines)?;
+                i += 1;
+                json_skip(s, at);
+                if s.get(*at)? == &b',' {
+                    *at += 1;
+                }
+            }
+        }
+        b'"' => json_string(s, at).map(|_| ()),
+        _ => {
+            while *at < s.len() && !b",}]\n\r\t ".contains(&s[*at]) {
+                *at += 1;
+            }
+            Some(())
+        }
+    }
+}
+
+fn json_skip(s: &[u8], at: &mut usize) {
+    while let Some(c) = s.get(*at) {
+        let comment = *c == b'#' || (*c == b'/' && s.get(*at + 1) == Some(&b'/'));
+        if comment {
+            while s.get(*at).is_some_and(|c| *c != b'\n') {
+                *at += 1;
+            }
+        } else if c.is_ascii_whitespace() {
+            *at += 1;
+        } else {
+            return;
+        }
+    }
+}
+
+fn json_string(s: &[u8], at: &mut usize) -> Option<String> {
+    let start = *at;
+    (s.get(*at)? == &b'"').then_some(())?;
+    *at += 1;
+    loop {
+        match s.get(*at)? {
+            b'\\' => *at += 2,
+            b'"' => break,
+            _ => *at += 1,
+        }
+    }
+    *at += 1;
+    serde_json::from_slice(&s[start..*at]).ok()
+}
+
+/// Block style YAML, a flow list or map counts as one line.
+fn yaml(content: &str, lines: &mut HashMap<String, usize>) {
+    // (indent, path, whether the key has its value on the following lines)
+    let mut open: Vec<(usize, String, bool)> = vec![];
+    let mut items: HashMap<String, usize> = HashMap::new();
+    let mut block_scalar: Option<usize> = None;
+
+    for (n, line) in content.lines().enumerate() {
+        let text = line.trim_start();
+        let mut indent = line.len() - text.len();
+        if text.is_empty() || text.starts_with('#') || text.starts_with("---") {
+            continue;
+        }
+        if let Some(at) = block_scalar {
+            if indent > at {
+                continue;
+            }
+            block_scalar = None;
+        }
+
+        let mut text = text;
+        let mut is_item = text == "-" || text.starts_with("- ");
+        while let Some(&(at, _, key_open)) = open.last() {
+            if at > indent || (at == indent && !(is_item && key_open)) {
+                open.pop();
+            } else {
+                break;
+            }
+        }
+
+        // `- - a` and `- key: a` carry on after the dash
+        while is_item {
+            let parent = open.last().map(|(_, p, _)| p.clone()).unwrap_or_default();
+            let i = items.entry(parent.clone()).or_default();
+            let path = format!("{}[{}]", parent, i);
+            *i += 1;
+            lines.insert(path.clone(), n + 1);
+            open.push((indent, path, false));
+
+            let rest = text[1..].trim_start();
+            indent += text.len() - rest.len();
+            text = rest;
+            is_item = text == "-" || text.starts_with("- ");
+        }
+
+        let Some((key, value)) = yaml_key(text) else {
+            continue;
+        };
+        let parent = open.last().map(|(_, p, _)| p.clone()).unwrap_or_default();
+        let path = child(&parent, &key);
+        lines.insert(path.clone(), n + 1);
+        if value.starts_with('|') || value.starts_with('>') {
+            block_scalar = Some(indent);
+        }
+        open.push((indent, path, value.is_empty() || value.starts_with('#')));
+    }
+}
+
+/// `key: value` split at the first `:` outside of quotes, with the quotes of
+/// the key taken off.
+fn yaml_key(text: &str) -> Option<(String, &str)> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if i == 0 => quote = Some(c),
+            (None, '{' | '[') if i == 0 => return None,
+            (None, ':') => {
+                let value = &text[i + 1..];
+                if !value.is_empty() && !value.starts_with(' ') {
+                    continue;
+                }
+                let key = text[..i].trim().trim_matches(['"', '\'']);
+                return Some((key.to_string(), value.trim()));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::locate::Source;
+
+    #[test]
+    fn test_locate() {
+        let toml = r#"
+mapping_file = "dwd"
+
+[retry]
+base_delay = "dwd"
+
+[[routes]]
+project = "dwd_*"
+channels = ["ads",
+  "dwd"]
+
+[rotations.platform]
+members = ["a"]
+"#;
+        let yaml = r#"
+mapping_file: dwd
+retry:
+  base_delay: dwd  # the same value
+routes:
+- project: "dwd_*"
+  channels:
+    - ads
+    - dwd
+rotations:
+  platform:
+    members: [a]
+"#;
+        let json = r#"{
+  "mapping_file": "dwd",
+  // the same value
+  "retry": {"base_delay": "dwd"},
+  "routes": [
+    {"project": "dwd_*",
+     "channels": ["ads",
+       "dwd"]}
+  ],
+  "rotations": {"platform": {"members": ["a"]}}
+}"#;
+        let cases = [
+            (toml, "toml", [2, 5, 10, 13, 12]),
+            (yaml, "yaml", [2, 4, 9, 12, 11]),
+            (json, "json", [2, 4, 8, 10, 10]),
+        ];
+        for (content, ext, expected) in cases {
+            let source = Source::new(content, ext);
+            let found = [
+                source.line_of("mapping_file", "dwd"),
+                source.line_of("retry.base_delay", "dwd"),
+                source.line_of("routes[0].channels[1]", "dwd"),
+                source.line_of("rotations.platform.members", ""),
+                source.line_of("rotations.platform.start", ""),
+            ];
+            assert_eq!(found, expected.map(Some), "{}", ext);
+            // not the value in the file, it comes from elsewhere
+            assert_eq!(source.line_of("retry.base_delay", "1s"), None, "{}", ext);
+            assert_eq!(source.line_of("retry.max_delay", "1s"), None, "{}", ext);
+        }
+    }
+}
@@ -3,12 +3,34 @@ extern crate alloc;
 mod monitor;
 mod utli;
 
+mod apipoll;
+mod azkaban;
 mod bean;
+mod callback;
+mod check;
 mod config;
+mod contact;
+mod digest;
+mod directory;
+mod escalate;
+mod flapping;
 mod gitblame;
+mod identity;
+mod link;
+mod locate;
 mod notice;
+mod oncall;
 mod parseflow;
+mod ratelimit;
+mod remediate;
+mod route;
+mod severity;
+mod silence;
+mod spool;
+mod state;
 mod style;
+mod team;
+mod validate;
 mod build;
 
 use crate::bean::InitConfig;
@@ -17,6 +39,7 @@ use log::info;
 use monitor::AzkabanMonitor as Monitor;
 use serde_json::json;
 use std::env;
+use std::path::PathBuf;
 use std::time::Duration;
 use tokio::time;
 #[tokio::main]
@@ -27,17 +50,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load environment variables
     dotenv().ok();
 
-    InitConfig::do_parse()?;
+    let (config_path, args) = config_arg(env::args().skip(1).collect())?;
+    InitConfig::do_parse(config_path.as_deref())?;
 
-    // Create monitor instance
-    let monitor = Monitor::new()?;
+    match args.first().map(String::as_str) {
+        Some("serve") => {
+            info!("Starting card callback server...");
+            callback::serve().await?;
+            return Ok(());
+        }
+        Some("silence") => {
+            silence::cli(&args[1..]).await?;
+            return Ok(());
+        }
+        Some("check-config") => {
+            check::run(InitConfig::global()).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // One monitor per Azkaban instance
+    let monitors = Monitor::all(InitConfig::global())?;
 
     info!("Starting Azkaban monitoring service...");
 
     // Check for failed tasks
-    if let Err(e) = monitor.run().await {
+    if let Err(e) = monitor::run_all(InitConfig::global(), monitors).await {
         log::error!("Error checking failed tasks: {}", e);
     }
 
     Ok(())
 }
+
+/// Takes `--config <path>` or `--config=<path>` out of the arguments.
+fn config_arg(args: Vec<String>) -> Result<(Option<PathBuf>, Vec<String>), String> {
+    let mut path = None;
+    let mut rest = vec![];
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        if arg == "--config" {
+            let value = it.next().ok_or("--config needs a path")?;
+            path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(PathBuf::from(value));
+        } else {
+            rest.push(arg);
+        }
+    }
+    Ok((path, rest))
+}
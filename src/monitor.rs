@@ -1,38 +1,149 @@
-use crate::bean::{InitConfig, Job, Task};
+use crate::apipoll;
+use crate::azkaban::{AzkabanClient, ExecFlow};
+use crate::bean::{FeishuUrl, InitConfig, Job, ProjectTasks, Severity, Silence, Task, Unowned};
 use crate::config::read_config;
-use crate::notice::send_with_struct_data;
+use crate::contact;
+use crate::digest;
+use crate::digest::DigestStore;
+use crate::directory::Directory;
+use crate::escalate;
+use crate::flapping;
+use crate::flapping::{History, JobRuns, Run};
+use crate::identity;
+use crate::notice::{
+    deliver, is_spooled, render_flaky_report, render_resolved, render_silenced, render_team_report,
+    render_unresolved_report, replay_spool, send_with_struct_data, tag_instance, CardOptions,
+};
+use crate::oncall;
 use crate::parseflow::parse_project_file;
-use crate::utli::{core_sql, decode_field, duration, get_datetime};
+use crate::remediate::remediate;
+use crate::route::route;
+use crate::severity;
+use crate::silence;
+use crate::state;
+use crate::state::{alert_key, job_key, Alert, AlertState, Recovery};
+use crate::team;
+use crate::utli::{
+    core_sql, decode_field, duration, format_duration_chinese, get_datetime, history_sql,
+    lock_file, log_excerpt, log_sql, parse_duration, success_sql,
+};
 use alloc::string::String;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use flate2::read::GzDecoder;
+use futures::future::join_all;
 use mysql::prelude::*;
 use mysql::*;
 use reqwest::Client;
 use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const LOG_EXCERPT_LINES: usize = 20;
+const LOG_MAX_BYTES: usize = 1024 * 1024;
+
+/// Instances poll their databases side by side, what comes after (state,
+/// silences, contact cache, sending) goes one instance at a time.
+static RUN_LOCK: Mutex<()> = Mutex::const_new(());
 
 pub struct AzkabanMonitor {
-    pool: Pool,
+    /// `None` polls through `azkaban` instead
+    pool: Option<Pool>,
     config: InitConfig,
     client: Client,
+    /// logs come from the web api when the database has none, and everything
+    /// without a database
+    azkaban: Option<AzkabanClient>,
+    /// `None` without `instances` in the config
+    instance: Option<String>,
 }
 
 impl AzkabanMonitor {
-    pub fn new() -> Result<Self> {
-        let config: &InitConfig = InitConfig::global();
-        let db_url = &config.db_full_url as &str;
+    pub fn new(config: InitConfig, instance: Option<String>) -> Result<Self> {
+        let db_url = config.db_full_url.trim();
 
-        let pool = Pool::new(db_url)?;
+        let pool = match db_url {
+            "" => None,
+            url => Some(Pool::new(url)?),
+        };
         let client = Client::new();
+        let azkaban = match config.azkaban_api {
+            Some(_) => Some(AzkabanClient::from_config(&config)?),
+            None => None,
+        };
+        if pool.is_none() && azkaban.is_none() {
+            return Err(anyhow!("Neither db_full_url nor azkaban_api is set"));
+        }
 
         Ok(Self {
-            pool: pool,
-            config: config.clone(),
+            pool,
+            config,
             client: client,
+            azkaban,
+            instance,
         })
     }
 
-    async fn process_execute_record(&self) -> Result<Vec<Task>> {
-        let mut conn = self.pool.get_conn()?;
+    /// One monitor per configured instance, or one for the top level config.
+    pub fn all(config: &InitConfig) -> Result<Vec<Self>> {
+        if config.instances.is_empty() {
+            return Ok(vec![Self::new(config.clone(), None)?]);
+        }
+        config
+            .instances
+            .iter()
+            .map(|i| {
+                Self::new(config.for_instance(i), Some(i.name.clone()))
+                    .map_err(|e| anyhow!("Instance {}: {}", i.name, e))
+            })
+            .collect()
+    }
+
+    /// The polling without a database, every call returns what `azkaban`
+    /// answers for the flows in the cron dirs.
+    fn api(&self) -> Result<&AzkabanClient> {
+        self.azkaban
+            .as_ref()
+            .ok_or_else(|| anyhow!("azkaban_api is not configured"))
+    }
+
+    /// Executions since `since` when polling through the api, fetched once
+    /// per run for the failures, recoveries and history. Empty with a database.
+    async fn poll_api(
+        &self,
+        parse_jobs: &HashMap<String, HashMap<String, HashMap<String, Job>>>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ExecFlow>> {
+        match &self.pool {
+            Some(_) => Ok(vec![]),
+            None => apipoll::executions(self.api()?, parse_jobs, since).await,
+        }
+    }
+
+    async fn process_execute_record(&self, execs: &[ExecFlow]) -> Result<Vec<Task>> {
+        let Some(pool) = &self.pool else {
+            let api = self.api()?;
+            let since = Utc::now() - chrono::Duration::hours(24);
+            let mut tasks = apipoll::failures(execs, since, self.instance.as_ref());
+            println!("Total results: {}", tasks.len());
+            for t in tasks.iter_mut() {
+                let log = api
+                    .fetch_exec_job_logs(&t.exec_id, &t.job_id, t.attempt, LOG_MAX_BYTES)
+                    .await
+                    .unwrap_or_else(|e| {
+                        println!(
+                            "Warning: Can't fetch log for {}.{}: {}",
+                            t.exec_id, t.job_id, e
+                        );
+                        String::new()
+                    });
+                t.log_excerpt = log_excerpt(&log, LOG_EXCERPT_LINES);
+            }
+            return Ok(tasks);
+        };
+        let mut conn = pool.get_conn()?;
 
         let query = core_sql().await.unwrap_or_default();
 
@@ -47,6 +158,7 @@ impl AzkabanMonitor {
             let flow_id: String = row.get("flow_id").unwrap_or_default();
             let job_id: String = row.get("job_id").unwrap_or_default();
             let attempt: u8 = row.get("attempt").unwrap_or_default();
+            let status: i32 = row.get("status").unwrap_or_default();
 
             println!(
                 "Processing task: project={}, exec_id={}, job_id={}",
@@ -64,12 +176,21 @@ impl AzkabanMonitor {
 
             let end_time = get_datetime(&row, "end_time").await.unwrap_or_default();
 
+            let log = self
+                .fetch_log(&mut conn, &exec_id, &job_id, attempt)
+                .await
+                .unwrap_or_else(|e| {
+                    println!("Warning: Can't fetch log for {}.{}: {}", exec_id, job_id, e);
+                    String::new()
+                });
+
             let task = Task {
                 exec_id: exec_id,
                 project_name: project_name,
                 flow_id: flow_id,
                 job_id: job_id,
                 attempt: attempt,
+                status,
                 start_time: start_time,
                 end_time: end_time,
                 input_params: input_params,
@@ -77,6 +198,12 @@ impl AzkabanMonitor {
                 owner: "".to_string(),
                 duration: duration(end_time, start_time),
                 desc: "".to_string(),
+                source_url: None,
+                unowned: None,
+                team: None,
+                instance: self.instance.clone(),
+                severity: Default::default(),
+                log_excerpt: log_excerpt(&log, LOG_EXCERPT_LINES),
             };
 
             result.push(task);
@@ -85,54 +212,308 @@ impl AzkabanMonitor {
         Ok(result)
     }
 
-    async fn generate_message(
-        tasks: HashMap<String, HashMap<String, Vec<Task>>>,
+    async fn fetch_log(
+        &self,
+        conn: &mut PooledConn,
+        exec_id: &str,
+        job_id: &str,
+        attempt: u8,
     ) -> Result<String> {
-        let mut messages = vec![];
+        let query = log_sql().await.unwrap_or_default();
 
-        for t in tasks {
-            let project = t.0;
-            let flows = t.1;
-            for f in flows {
-                let flow = f.0;
-                let tasks = f.1;
-                let message = Self::do_generate_message(flow, tasks).await?;
-                messages.push(message);
+        let row: Option<(Vec<u8>, i32)> = conn.exec_first(query, (exec_id, job_id, attempt))?;
+
+        let Some((bytes, enc_type)) = row else {
+            return match &self.azkaban {
+                Some(api) => {
+                    api.fetch_exec_job_logs(exec_id, job_id, attempt, LOG_MAX_BYTES)
+                        .await
+                }
+                None => Ok(String::new()),
+            };
+        };
+
+        // enc_type 2 is GZIP, 1 is PLAIN
+        if enc_type == 2 {
+            let mut s = String::new();
+            GzDecoder::new(&bytes[..]).read_to_string(&mut s)?;
+            Ok(s)
+        } else {
+            Ok(String::from_utf8_lossy(&bytes).to_string())
+        }
+    }
+
+    async fn fetch_recoveries(&self, execs: &[ExecFlow]) -> Result<HashMap<String, Recovery>> {
+        let Some(pool) = &self.pool else {
+            return Ok(apipoll::recoveries(execs));
+        };
+        let mut conn = pool.get_conn()?;
+
+        let query = success_sql().await.unwrap_or_default();
+        let rows: Vec<Row> = conn.query(query)?;
+
+        let mut result = HashMap::new();
+        for row in rows {
+            let project: String = row.get("name").unwrap_or_default();
+            let flow: String = row.get("flow_id").unwrap_or_default();
+            let job: String = row.get("job_id").unwrap_or_default();
+
+            let recovery = Recovery {
+                exec_id: row.get("exec_id").unwrap_or_default(),
+                submit_user: row.get("submit_user").unwrap_or_default(),
+                end_time: get_datetime(&row, "end_time").await.unwrap_or_default(),
+            };
+            result.insert(job_key(&project, &flow, &job), recovery);
+        }
+
+        Ok(result)
+    }
+
+    async fn notify_resolved(&self, resolved: Vec<(Alert, Recovery)>) -> usize {
+        let mut groups: HashMap<(String, String), Vec<(Alert, Recovery)>> = HashMap::new();
+        for (alert, recovery) in resolved {
+            println!("Resolved: {}", alert_key(&alert.task));
+            for n in &alert.notified {
+                groups
+                    .entry((n.url.clone(), n.user_id.clone()))
+                    .or_default()
+                    .push((alert.clone(), recovery.clone()));
             }
         }
 
-        Ok(messages.join("----------"))
+        let mut failed = 0;
+        for ((url, user_id), items) in groups {
+            let webhook = self
+                .config
+                .webhook_by_url(&url)
+                .cloned()
+                .unwrap_or(FeishuUrl::Plain(url));
+            let mut card =
+                render_resolved(&user_id, &items, self.config.azkaban_url.as_deref()).await;
+            tag_instance(&mut card, self.instance.as_deref());
+            if let Err(e) = deliver(&webhook, card).await {
+                println!("Error: {}", e);
+                failed += 1;
+            }
+        }
+        failed
     }
 
-    async fn do_generate_message(flow: String, tasks: Vec<Task>) -> Result<String> {
-        let mut multi_detail: Vec<String> = vec![];
+    async fn fetch_history(
+        &self,
+        since: DateTime<Utc>,
+        parse_jobs: &HashMap<String, HashMap<String, HashMap<String, Job>>>,
+    ) -> Result<History> {
+        let Some(pool) = &self.pool else {
+            let execs = apipoll::executions(self.api()?, parse_jobs, since).await?;
+            return Ok(apipoll::history(&execs, since));
+        };
+        let mut conn = pool.get_conn()?;
 
-        for x in tasks {
-            let single = format!(
-                "
-                **flow_id** : {}\n\
-                **job_id**: {}\n\
-                **attempt**: {}\n\
-                **start_time**: {}\n\
-                **end_time**: {}\n\
-                ",
-                x.flow_id, x.job_id, x.attempt, x.start_time, x.end_time
-            )
-            .trim_start()
-            .to_string();
-            multi_detail.push(single)
+        let query = history_sql().await.unwrap_or_default();
+        let rows: Vec<Row> = conn.exec(query, (since.timestamp_millis(),))?;
+
+        let mut result: History = HashMap::new();
+        for row in rows {
+            let project: String = row.get("name").unwrap_or_default();
+            let flow: String = row.get("flow_id").unwrap_or_default();
+            let job: String = row.get("job_id").unwrap_or_default();
+
+            let run = Run {
+                exec_id: row.get("exec_id").unwrap_or_default(),
+                attempt: row.get("attempt").unwrap_or_default(),
+                status: row.get("status").unwrap_or_default(),
+                end_time: get_datetime(&row, "end_time").await.unwrap_or_default(),
+            };
+            result
+                .entry(job_key(&project, &flow, &job))
+                .or_insert_with(|| JobRuns {
+                    project,
+                    flow,
+                    job,
+                    runs: vec![],
+                })
+                .runs
+                .push(run);
         }
 
-        let details = multi_detail.join("\n");
+        Ok(result)
+    }
 
-        Ok(format!(
-            "
-            flow:**{}**下有任务异常:\n{}
-            ",
-            flow, details
-        )
-        .trim_start()
-        .to_string())
+    /// Weekly report of the flapping jobs to their owners.
+    async fn flaky_report(
+        &self,
+        history: &History,
+        state: &mut AlertState,
+        directory: &Directory,
+        mappings: HashMap<String, String>,
+        parse_jobs: HashMap<String, HashMap<String, HashMap<String, Job>>>,
+    ) -> usize {
+        let config = &self.config.flapping;
+        let Some(at) = config.report_at.as_deref() else {
+            return 0;
+        };
+        match flapping::report_due(at, state.flaky_report_at, &Local::now()) {
+            Ok(true) => {}
+            Ok(false) => return 0,
+            Err(e) => {
+                println!("Warning: {}", e);
+                return 0;
+            }
+        }
+
+        let now = Utc::now();
+        state.flaky_report_at = Some(now);
+
+        let mut lines = HashMap::new();
+        let mut tasks = vec![];
+        for (key, job) in history {
+            let rates = flapping::rates(config, &job.runs, now);
+            if !flapping::is_flapping(config, &rates) {
+                continue;
+            }
+            if let Some(mut t) = flapping::flaky_task(job) {
+                t.instance.clone_from(&self.instance);
+                let spark = flapping::sparkline(&job.runs, config.history);
+                lines.insert(key.clone(), (flapping::describe(&rates), spark));
+                tasks.push(t);
+            }
+        }
+        println!("Flaky job report: {} jobs", tasks.len());
+        if tasks.is_empty() {
+            return 0;
+        }
+
+        let tasks = match self
+            .merge_git_and_azkaban(mappings.clone(), parse_jobs, tasks)
+            .await
+        {
+            Ok(mut t) => {
+                oncall::assign(&self.config, &mappings, &mut t, now);
+                team::assign(&self.config, directory, &mappings, &mut t, now);
+                t
+            }
+            Err(e) => {
+                println!("Error: Can't resolve owners of flaky jobs: {}", e);
+                return 1;
+            }
+        };
+
+        let mut failed = 0;
+        for delivery in route(&self.config, tasks) {
+            let tasks: Vec<Task> = delivery
+                .projects
+                .into_values()
+                .flat_map(|f| f.into_values())
+                .flatten()
+                .collect();
+            let mut card = render_flaky_report(&delivery.user_id, &tasks, &lines).await;
+            tag_instance(&mut card, self.instance.as_deref());
+            if let Err(e) = deliver(&delivery.webhook, card).await {
+                println!("Error: {}", e);
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    /// Send the due escalation tiers of the alerts still open after this run.
+    async fn escalate(
+        &self,
+        state: &mut AlertState,
+        mappings: &HashMap<String, String>,
+        tasks: &[Task],
+        opts: &CardOptions,
+        now: DateTime<Utc>,
+    ) -> usize {
+        let mut cards: HashMap<(String, String), (FeishuUrl, ProjectTasks)> = HashMap::new();
+        let mut opts = opts.clone();
+
+        for t in tasks {
+            let key = alert_key(t);
+            let (Some(policy), Some(alert)) = (
+                escalate::policy_for(&self.config, t),
+                state.alerts.get(&key),
+            ) else {
+                continue;
+            };
+
+            let due = escalate::due_tiers(policy, alert, now);
+            let Some(&last) = due.last() else {
+                continue;
+            };
+            println!("Escalating {} to tier {}", key, last + 1);
+
+            let mut targets = vec![];
+            for i in &due {
+                let tier = &policy.tiers[*i];
+                targets.extend(escalate::targets(&self.config, mappings, tier, alert, now));
+            }
+            for (webhook, user_id) in targets {
+                cards
+                    .entry((webhook.url().to_string(), user_id.clone()))
+                    .or_insert_with(|| (webhook.clone(), HashMap::new()))
+                    .1
+                    .entry(t.project_name.clone())
+                    .or_default()
+                    .entry(t.flow_id.clone())
+                    .or_default()
+                    .push(t.clone());
+                state.record_notified(t, webhook.url(), &user_id);
+            }
+
+            let Some(alert) = state.alerts.get_mut(&key) else {
+                continue;
+            };
+            alert.escalated.extend(due);
+            let waited = (now - alert.first_notified_at.unwrap_or(now))
+                .to_std()
+                .unwrap_or_default();
+            let notes = opts.notes.entry(key).or_default();
+            notes.retain(|n| !n.starts_with("📣"));
+            notes.insert(
+                0,
+                format!(
+                    "⏫ 已 {} 未确认, 升级到第 {} 级",
+                    format_duration_chinese(waited),
+                    last + 1
+                ),
+            );
+            notes.push(format!("📣 已通知: {}", alert.notified_line()));
+        }
+
+        let mut failed = 0;
+        for ((_, user_id), (webhook, details)) in cards {
+            let sent =
+                send_with_struct_data(&webhook, &user_id, &details, &opts, &mut state.cards).await;
+            if let Err(e) = sent {
+                println!("Error: {}", e);
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    async fn notify_silenced(&self, ended: Vec<(Silence, Vec<Task>)>) -> usize {
+        let mut failed = 0;
+        for (silence, tasks) in ended {
+            println!("Silence over: {} ({} alerts)", silence.key(), tasks.len());
+            for delivery in route(&self.config, tasks) {
+                let tasks: Vec<Task> = delivery
+                    .projects
+                    .into_values()
+                    .flat_map(|f| f.into_values())
+                    .flatten()
+                    .collect();
+                let mut card = render_silenced(&delivery.user_id, &silence, &tasks).await;
+                tag_instance(&mut card, self.instance.as_deref());
+                if let Err(e) = deliver(&delivery.webhook, card).await {
+                    println!("Error: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+        failed
     }
 
     async fn merge_git_and_azkaban(
@@ -143,6 +524,28 @@ impl AzkabanMonitor {
     ) -> Result<Vec<Task>> {
         let mut tasks = az_task;
         let mut jobs = git_job;
+        let aliases = &self.config.identities.aliases;
+
+        // authors the directory doesn't know, by email
+        let emails: Vec<String> = tasks
+            .iter()
+            .filter_map(|t| {
+                jobs.get(&t.project_name)
+                    .and_then(|flow| flow.get(&t.flow_id))
+                    .and_then(|job| job.get(&t.job_id))
+            })
+            .filter(|job| {
+                !job.owner_email.is_empty()
+                    && identity::owner_id(aliases, &name_mapping, &HashMap::new(), job).is_none()
+            })
+            .map(|job| job.owner_email.clone())
+            .collect();
+        let looked_up = contact::resolve(&self.config, &emails, Utc::now())
+            .await
+            .unwrap_or_else(|e| {
+                println!("Warning: Can't look up owners on Feishu: {}", e);
+                HashMap::new()
+            });
 
         for t in tasks.iter_mut() {
             let target_job = jobs
@@ -152,21 +555,29 @@ impl AzkabanMonitor {
 
             match target_job {
                 Some(job) => {
-                    let owner_name = &job.owner;
-                    t.owner = name_mapping
-                        .get(owner_name)
-                        .map(Clone::clone)
+                    let owner_name = identity::canonical(aliases, &job.owner, &job.owner_email);
+                    t.owner = identity::owner_id(aliases, &name_mapping, &looked_up, job)
+                        .cloned()
                         .unwrap_or_else(|| {
                             println!("Warning: No mapping found for owner '{}'", owner_name);
                             String::new()
                         });
-                    t.desc = job.desc.to_string()
+                    if t.owner.is_empty() {
+                        t.unowned = Some(if owner_name.is_empty() {
+                            Unowned::BlameEmpty
+                        } else {
+                            Unowned::MappingMissing(owner_name)
+                        });
+                    }
+                    t.desc = job.desc.to_string();
+                    t.source_url = job.source_url.clone();
                 }
                 None => {
                     println!(
                         "Warning: No matching job found for project '{}', flow '{}', job '{}'",
                         t.project_name, t.flow_id, t.job_id
                     );
+                    t.unowned = Some(Unowned::JobNotFound);
                 }
             }
         }
@@ -176,57 +587,467 @@ impl AzkabanMonitor {
 
     pub async fn run(&self) -> Result<()> {
         let mapping_file = &self.config.mapping_file as &str;
-        let mappings = read_config(mapping_file).await.unwrap_or_default();
+        let directory = read_config(mapping_file).await?;
+        let mappings = directory.name_mapping();
 
-        let parse_jobs = parse_project_file().await?;
+        let parse_jobs = parse_project_file(&self.config.target_cron_dir).await?;
+
+        // the api is asked once, for the longest window any of the below needs
+        let now = Utc::now();
+        let lookback = match self.config.flapping.enabled {
+            true => flapping::lookback(&self.config.flapping).unwrap_or_default(),
+            false => chrono::Duration::zero(),
+        };
+        let execs = self
+            .poll_api(&parse_jobs, now - lookback.max(chrono::Duration::days(7)))
+            .await?;
 
-        let mut tasks = self.process_execute_record().await?;
+        let mut tasks = self.process_execute_record(&execs).await?;
+        let recoveries = self.fetch_recoveries(&execs).await.unwrap_or_else(|e| {
+            println!("Warning: Can't fetch successful runs: {}", e);
+            HashMap::new()
+        });
 
+        let _run = RUN_LOCK.lock().await;
         tasks = self
-            .merge_git_and_azkaban(mappings, parse_jobs, tasks)
+            .merge_git_and_azkaban(mappings.clone(), parse_jobs.clone(), tasks)
             .await?;
+        // routes and teams may match on the severity
+        severity::assign(&self.config, &mut tasks);
+        oncall::assign(&self.config, &mappings, &mut tasks, now);
+        team::assign(&self.config, &directory, &mappings, &mut tasks, now);
 
-        if tasks.is_empty() {
+        let state_path = Path::new(&self.config.state.path);
+        let mut state = state::load(state_path).await?;
+
+        // already fixed by a later successful run
+        tasks.retain(|t| {
+            recoveries
+                .get(&alert_key(t))
+                .is_none_or(|r| r.end_time <= t.end_time)
+        });
+        for t in &tasks {
+            state.observe(t, now);
+        }
+        match parse_duration(&self.config.state.expire_after) {
+            Ok(max_age) => {
+                let expired = state.expire(max_age, now);
+                if expired > 0 {
+                    println!(
+                        "Expired {} alerts not seen failing for {:?}",
+                        expired, max_age
+                    );
+                }
+            }
+            Err(e) => println!("Warning: Bad state.expire_after: {}", e),
+        }
+        // acked or snoozed from the card
+        tasks.retain(|t| {
+            let muted = state
+                .alerts
+                .get(&alert_key(t))
+                .is_some_and(|a| a.muted(now));
+            if muted {
+                println!("Skip muted {}", alert_key(t));
+            }
+            !muted
+        });
+
+        let silence_path = Path::new(&self.config.silence_store);
+        let mut silences = silence::load(silence_path).await?;
+        let active = silence::all(&self.config, &silences);
+        tasks = silence::suppress(&active, &mut silences, tasks, now);
+        let mut failed = self
+            .notify_silenced(silence::ended(
+                &active,
+                &mut silences,
+                self.instance.as_deref(),
+                now,
+            ))
+            .await;
+        save_silences(silence_path, silences, now).await?;
+
+        tasks = remediate(&self.config, self.azkaban.as_ref(), &mut state, tasks, now).await;
+
+        let resolved = state.resolve(&recoveries);
+        if self.config.state.notify_resolved && !resolved.is_empty() {
+            failed += self.notify_resolved(resolved).await;
+        }
+
+        let history = if self.config.flapping.enabled {
+            let since = flapping::lookback(&self.config.flapping).map(|l| now - l);
+            match since {
+                Ok(since) if self.pool.is_none() => Ok(apipoll::history(&execs, since)),
+                Ok(since) => self.fetch_history(since, &parse_jobs).await,
+                Err(e) => Err(e),
+            }
+            .unwrap_or_else(|e| {
+                println!("Warning: Can't fetch job history: {}", e);
+                HashMap::new()
+            })
+        } else {
+            HashMap::new()
+        };
+        failed += self
+            .flaky_report(
+                &history,
+                &mut state,
+                &directory,
+                mappings.clone(),
+                parse_jobs,
+            )
+            .await;
+
+        if tasks.is_empty() && !self.config.digest.enabled {
             println!("No failed tasks found jump");
+            save_state(state_path, state).await?;
             return Ok(());
         }
 
         println!("Get total {} need-alert task ", tasks.len());
 
-        let mut groups: HashMap<String, HashMap<String, HashMap<String, Vec<Task>>>> =
-            HashMap::new();
+        let digest_cfg = &self.config.digest;
+        let store_path = Path::new(&digest_cfg.store);
+        let mut store = if digest_cfg.enabled {
+            digest::load(store_path).await?
+        } else {
+            DigestStore::default()
+        };
 
-        for t in tasks {
-            let owner = t.owner.clone();
-            let project = t.project_name.clone();
-            let flow = t.flow_id.clone();
-
-            groups
-                .entry(owner)
-                .or_insert_with(HashMap::new)
-                .entry(project)
-                .or_insert_with(HashMap::new)
-                .entry(flow)
-                .or_insert_with(Vec::new)
-                .push(t)
-        }
-
-        for u in groups {
-            let user_id = u.0.as_str();
-            let projects = u.1;
-
-            if user_id.is_empty() {
-                println!("User id is empty jump all the task {:?}", projects);
-                continue;
+        let mut opts = CardOptions::from_config(&self.config);
+        opts.instance.clone_from(&self.instance);
+        for t in &tasks {
+            let key = alert_key(t);
+            let mut notes = vec![];
+            if let Some(alert) = state.alerts.get(&key) {
+                notes.extend(alert.notes(opts.azkaban_url.as_deref()));
+            }
+            if let Some(job) = history.get(&key) {
+                notes.extend(flapping::note(&self.config.flapping, &job.runs, now));
+            }
+            if let Some(team) = &t.team {
+                notes.push(format!("👥 团队: {}", team));
             }
+            if t.severity != Severity::Warning {
+                notes.push(format!("🏷️ 级别: {}", t.severity.name()));
+            }
+            opts.notes.insert(key, notes);
+        }
+        let escalate: Vec<Task> = tasks
+            .iter()
+            .filter(|t| escalate::policy_for(&self.config, t).is_some())
+            .cloned()
+            .collect();
+        for mut delivery in route(&self.config, tasks) {
+            if digest_cfg.enabled {
+                let (now, later) = digest::partition(digest_cfg, delivery);
+                digest::hold(&mut store, &now.webhook, &now.user_id, later);
+                delivery = now;
+            }
+            let result = send_with_struct_data(
+                &delivery.webhook,
+                &delivery.user_id,
+                &delivery.projects,
+                &opts,
+                &mut state.cards,
+            )
+            .await;
+            if let Err(e) = &result {
+                println!("Error: {}", e);
+                failed += 1;
+            }
+            // held tasks count as notified once their digest goes out
+            if result.as_ref().err().is_none_or(is_spooled) {
+                for t in delivery
+                    .projects
+                    .values()
+                    .flat_map(|f| f.values())
+                    .flatten()
+                {
+                    state.record_notified(t, delivery.webhook.url(), &delivery.user_id);
+                }
+            }
+        }
 
-            let urls = &self.config.feishu_url;
-            for url in urls {
-                send_with_struct_data(url, user_id, &projects)
-                    .await
-                    .expect("");
+        failed += self
+            .escalate(&mut state, &mappings, &escalate, &opts, now)
+            .await;
+
+        if digest_cfg.enabled {
+            let due = digest::flush(&self.config, &mut store, &Local::now()).await;
+            for (p, webhook, mut card) in due {
+                tag_instance(&mut card, self.instance.as_deref());
+                let result = deliver(&webhook, card).await;
+                if let Err(e) = &result {
+                    println!("Error: {}", e);
+                    failed += 1;
+                }
+                if result.as_ref().err().is_none_or(is_spooled) {
+                    for t in &p.tasks {
+                        state.record_notified(t, &p.url, &p.user_id);
+                    }
+                }
             }
+            digest::save(store_path, &store).await?;
+        }
+        save_state(state_path, state).await?;
+        if failed > 0 {
+            println!("{} messages failed and were spooled", failed);
         }
         Ok(())
     }
 }
+
+/// Replay the spool once, run every instance, then the reports over all of
+/// them, failing if any of that did.
+pub async fn run_all(config: &InitConfig, monitors: Vec<AzkabanMonitor>) -> Result<()> {
+    if let Err(e) = replay_spool().await {
+        println!("Error replaying spool: {}", e);
+    }
+
+    let monitors: Vec<Arc<AzkabanMonitor>> = monitors.into_iter().map(Arc::new).collect();
+    let runs: Vec<_> = monitors
+        .iter()
+        .cloned()
+        .map(|m| tokio::spawn(async move { (m.instance.clone(), m.run().await) }))
+        .collect();
+    let mut failed = vec![];
+    for run in join_all(runs).await {
+        match run? {
+            (_, Ok(())) => {}
+            (Some(name), Err(e)) => failed.push(format!("{}: {}", name, e)),
+            (None, Err(e)) => failed.push(e.to_string()),
+        }
+    }
+
+    match global_reports(config, &monitors).await {
+        Ok(0) => {}
+        Ok(n) => println!("{} reports failed and were spooled", n),
+        Err(e) => failed.push(format!("reports: {}", e)),
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!(failed.join("\n")));
+    }
+    Ok(())
+}
+
+/// Weekly list of blamed authors no owner was found for, to the unowned channel.
+async fn unresolved_report(
+    config: &InitConfig,
+    monitors: &[Arc<AzkabanMonitor>],
+    state: &mut AlertState,
+    mappings: &HashMap<String, String>,
+) -> usize {
+    let Some(at) = config.identities.report_at.as_deref() else {
+        return 0;
+    };
+    match flapping::report_due(at, state.unresolved_report_at, &Local::now()) {
+        Ok(true) => {}
+        Ok(false) => return 0,
+        Err(e) => {
+            println!("Warning: {}", e);
+            return 0;
+        }
+    }
+    state.unresolved_report_at = Some(Utc::now());
+
+    // instances often share their cron dirs
+    let mut cron_dirs: Vec<String> = monitors
+        .iter()
+        .flat_map(|m| m.config.target_cron_dir.clone())
+        .collect();
+    cron_dirs.sort();
+    cron_dirs.dedup();
+    let parse_jobs = match parse_project_file(&cron_dirs).await {
+        Ok(p) => p,
+        Err(e) => {
+            println!(
+                "Error: Can't parse the cron dirs for the author report: {}",
+                e
+            );
+            return 1;
+        }
+    };
+    let parse_jobs = &parse_jobs;
+
+    let aliases = &config.identities.aliases;
+    let emails: Vec<String> = parse_jobs
+        .values()
+        .flat_map(HashMap::values)
+        .flat_map(HashMap::values)
+        .filter(|job| {
+            !job.owner_email.is_empty()
+                && identity::owner_id(aliases, mappings, &HashMap::new(), job).is_none()
+        })
+        .map(|job| job.owner_email.clone())
+        .collect();
+    let looked_up = contact::resolve(config, &emails, Utc::now())
+        .await
+        .unwrap_or_else(|e| {
+            println!("Warning: Can't look up owners on Feishu: {}", e);
+            HashMap::new()
+        });
+
+    let unresolved = identity::unresolved(aliases, mappings, &looked_up, parse_jobs);
+    println!("Unresolved author report: {} authors", unresolved.len());
+    if unresolved.is_empty() {
+        return 0;
+    }
+    let Some(webhook) = &config.fallback.unowned_channel else {
+        println!("Warning: identities.report_at is set but fallback.unowned_channel is not");
+        return 0;
+    };
+
+    let card = render_unresolved_report(&unresolved).await;
+    match deliver(webhook, card).await {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// Weekly failure load of each team to its channel, over all instances.
+async fn team_report(
+    config: &InitConfig,
+    monitors: &[Arc<AzkabanMonitor>],
+    state: &mut AlertState,
+    directory: &Directory,
+    mappings: &HashMap<String, String>,
+) -> usize {
+    let report = &config.team_report;
+    let Some(at) = report.report_at.as_deref() else {
+        return 0;
+    };
+    match flapping::report_due(at, state.team_report_at, &Local::now()) {
+        Ok(true) => {}
+        Ok(false) => return 0,
+        Err(e) => {
+            println!("Warning: {}", e);
+            return 0;
+        }
+    }
+
+    let now = Utc::now();
+    let since = match parse_duration(&report.window) {
+        Ok(w) => now - chrono::Duration::from_std(w).unwrap_or_default(),
+        Err(e) => {
+            println!("Warning: Bad team report window: {}", e);
+            return 0;
+        }
+    };
+
+    // jobs of every instance, told apart by the instance in their key
+    let aliases = &config.identities.aliases;
+    let no_lookups = HashMap::new();
+    let mut history: History = HashMap::new();
+    let mut teams: HashMap<String, String> = HashMap::new();
+    for m in monitors {
+        let found = match parse_project_file(&m.config.target_cron_dir).await {
+            Ok(parse_jobs) => m
+                .fetch_history(since, &parse_jobs)
+                .await
+                .map(|h| (h, parse_jobs)),
+            Err(e) => Err(e),
+        };
+        let (jobs, parse_jobs) = match found {
+            Ok(found) => found,
+            Err(e) => {
+                println!("Error: Can't fetch job history for the team report: {}", e);
+                return 1;
+            }
+        };
+        for (key, job) in jobs {
+            let key = match &m.instance {
+                Some(name) => format!("[{}] {}", name, key),
+                None => key,
+            };
+            let Some(run) = job.runs.last() else {
+                continue;
+            };
+            let mut task = job.task(run);
+            task.instance.clone_from(&m.instance);
+            if let Some(j) = parse_jobs
+                .get(&job.project)
+                .and_then(|flow| flow.get(&job.flow))
+                .and_then(|jobs| jobs.get(&job.job))
+            {
+                task.owner = identity::owner_id(aliases, mappings, &no_lookups, j)
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            if let Some(team) = team::team_of(config, directory, &task) {
+                teams.insert(key.clone(), team);
+            }
+            history.insert(key, job);
+        }
+    }
+    state.team_report_at = Some(now);
+
+    let loads = team::load(&history, |key| teams.get(key).cloned(), since);
+    println!("Team report: {} teams", loads.len());
+
+    let mut failed = 0;
+    for (name, load) in &loads {
+        let Some(webhook) = team::channel(config, name) else {
+            println!("Warning: team {} has no channel for its report", name);
+            continue;
+        };
+        let lead = config.teams.get(name).and_then(|t| t.lead.as_deref());
+        let lead = lead
+            .and_then(|l| oncall::resolve(config, mappings, l, now).ok())
+            .unwrap_or_default();
+        let card = render_team_report(&lead, name, &report.window, load).await;
+        if let Err(e) = deliver(webhook, card).await {
+            println!("Error: {}", e);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// Reports over all instances together, sent once however many instances
+/// there are. When they were last sent is kept in the top level state file.
+async fn global_reports(config: &InitConfig, monitors: &[Arc<AzkabanMonitor>]) -> Result<usize> {
+    if config.identities.report_at.is_none() && config.team_report.report_at.is_none() {
+        return Ok(0);
+    }
+    let directory = read_config(&config.mapping_file).await?;
+    let mappings = directory.name_mapping();
+
+    let path = Path::new(&config.state.path);
+    let mut state = state::load(path).await?;
+    let mut failed = unresolved_report(config, monitors, &mut state, &mappings).await;
+    failed += team_report(config, monitors, &mut state, &directory, &mappings).await;
+    save_state(path, state).await?;
+    Ok(failed)
+}
+
+/// Save what was silenced, keeping silences added from the cli meanwhile.
+async fn save_silences(
+    path: &Path,
+    mut store: silence::SilenceStore,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let _lock = lock_file(path).await?;
+    match silence::load(path).await {
+        Ok(on_disk) => {
+            store.silences = on_disk.silences;
+            silence::prune(&mut store, now);
+        }
+        Err(e) => println!("Warning: Can't reload silences: {}", e),
+    }
+    silence::save(path, &store).await
+}
+
+/// Save the state without losing acks and snoozes the callback server wrote meanwhile.
+async fn save_state(path: &Path, mut state: state::AlertState) -> Result<()> {
+    let _lock = lock_file(path).await?;
+    match state::load(path).await {
+        Ok(on_disk) => state.merge_actions(&on_disk),
+        Err(e) => println!("Warning: Can't reload alert state: {}", e),
+    }
+    state::save(path, &state).await
+}
@@ -1,60 +1,960 @@
-use crate::bean::Task;
+use crate::bean::{
+    CardLimits, FeishuUrl, InitConfig, ProjectTasks, Retry, Severity, Silence, Task,
+};
+use crate::link::{execution_url, flow_url, log_url};
+use crate::ratelimit;
+use crate::spool;
+use crate::spool::SpoolEntry;
+use crate::state::{alert_key, Alert, Recovery};
 use crate::style;
-use crate::style::{div_flow_and_project, div_message, hr};
+use crate::style::{
+    button, button_row, callback_button, collapsible_panel, column_set, div_flow_and_project,
+    div_message, hr,
+};
+use crate::team::Load;
+use crate::utli::{format_duration_chinese, parse_duration, status_name};
 use anyhow::{anyhow, Result};
-use reqwest::Client;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Local, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use style::{div_at_user, div_project};
 
+const LONG_DESC_LEN: usize = 80;
+/// rows of the most failing jobs on a team report
+const TEAM_REPORT_JOBS: usize = 10;
+
+/// What a card shows beside the tasks themselves.
+#[derive(Debug, Clone, Default)]
+pub struct CardOptions {
+    pub azkaban_url: Option<String>,
+    /// Ack / Snooze / Rerun callback buttons
+    pub actions: bool,
+    /// shown on the snooze button, e.g. `4h`
+    pub snooze: String,
+    /// alert key -> extra lines shown under the task
+    pub notes: HashMap<String, Vec<String>>,
+    /// Azkaban instance the tasks ran on, in the title and the callback buttons
+    pub instance: Option<String>,
+}
+
+impl CardOptions {
+    pub fn from_config(config: &InitConfig) -> Self {
+        CardOptions {
+            azkaban_url: config.azkaban_url.clone(),
+            actions: config.callback.is_some(),
+            snooze: config
+                .callback
+                .as_ref()
+                .map(|c| c.snooze.clone())
+                .unwrap_or_default(),
+            notes: HashMap::new(),
+            instance: None,
+        }
+    }
+}
+
+/// Send `details` as one or more cards, the alert keys behind the callback
+/// buttons of each go into `cards` by card id.
 pub async fn send_with_struct_data(
-    url: &str,
+    webhook: &FeishuUrl,
     user_id: &str,
     details: &HashMap<String, HashMap<String, Vec<Task>>>,
+    opts: &CardOptions,
+    cards: &mut HashMap<String, Vec<String>>,
 ) -> Result<(), anyhow::Error> {
-    let mut frame = template().await;
-
     if details.is_empty() {
         println!("Nothing to send for ");
         return Ok(());
     }
 
+    let config = InitConfig::global();
+    let rendered = build_cards(user_id, details, opts, &config.card).await;
+
+    let mut result = Ok(());
+    for mut card in rendered {
+        button_keys(&card, cards);
+        tag_instance(&mut card, opts.instance.as_deref());
+        if let Err(e) = deliver(webhook, card).await {
+            // a lost card is worth reporting over a spooled one
+            if !matches!(&result, Err(r) if !is_spooled(r)) {
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layout {
+    Full,
+    Summary,
+}
+
+/// One flow worth of tasks, the unit cards are split by.
+type Part = (String, String, Vec<Task>);
+
+async fn render(
+    layout: Layout,
+    user_id: &str,
+    details: &ProjectTasks,
+    opts: &CardOptions,
+) -> Value {
+    match layout {
+        Layout::Full => render_card(user_id, details, opts).await,
+        Layout::Summary => render_summary(user_id, details).await,
+    }
+}
+
+fn to_details(parts: &[Part]) -> ProjectTasks {
+    let mut details: ProjectTasks = HashMap::new();
+    for (project, flow, tasks) in parts {
+        details
+            .entry(project.clone())
+            .or_default()
+            .entry(flow.clone())
+            .or_default()
+            .extend(tasks.iter().cloned());
+    }
+    details
+}
+
+/// Number of card components, every object with a `tag` counts.
+fn count_elements(v: &Value) -> usize {
+    match v {
+        Value::Object(m) => {
+            m.contains_key("tag") as usize + m.values().map(count_elements).sum::<usize>()
+        }
+        Value::Array(a) => a.iter().map(count_elements).sum(),
+        _ => 0,
+    }
+}
+
+/// Short id of the card showing `keys`, carried by its callback buttons
+/// instead of every key on the card.
+pub fn card_id(keys: &[String]) -> String {
+    let mut keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    Sha256::digest(keys.join("\n").as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Card id -> alert keys, from the callback buttons found in `v`.
+fn button_keys(v: &Value, cards: &mut HashMap<String, Vec<String>>) {
+    match v {
+        Value::Object(m) => {
+            if let (Some(card), Some(key)) = (
+                m.get("card").and_then(Value::as_str),
+                m.get("key").and_then(Value::as_str),
+            ) {
+                let keys = cards.entry(card.to_string()).or_default();
+                if !keys.iter().any(|k| k == key) {
+                    keys.push(key.to_string());
+                }
+            }
+            m.values().for_each(|x| button_keys(x, cards));
+        }
+        Value::Array(a) => a.iter().for_each(|x| button_keys(x, cards)),
+        _ => {}
+    }
+}
+
+fn fits(card: &Value, limits: &CardLimits) -> bool {
+    let bytes = serde_json::to_vec(card)
+        .map(|b| b.len())
+        .unwrap_or(usize::MAX);
+    bytes <= limits.max_bytes && count_elements(&card["card"]["body"]) <= limits.max_elements
+}
+
+/// Render `details` into as few cards as fit the limits, labelled "(1/3)" when split.
+async fn build_cards(
+    user_id: &str,
+    details: &ProjectTasks,
+    opts: &CardOptions,
+    limits: &CardLimits,
+) -> Vec<Value> {
+    let total: usize = details
+        .values()
+        .flat_map(|f| f.values())
+        .map(Vec::len)
+        .sum();
+    let layout = if total > limits.summary_threshold {
+        println!("{} tasks for {}, sending a summary", total, user_id);
+        Layout::Summary
+    } else {
+        Layout::Full
+    };
+
+    let card = render(layout, user_id, details, opts).await;
+    if fits(&card, limits) {
+        return vec![card];
+    }
+
+    let mut pending: Vec<Part> = vec![];
+    let mut projects: Vec<_> = details.iter().collect();
+    projects.sort_by_key(|(p, _)| p.as_str());
+    for (project, flows) in projects {
+        let mut flows: Vec<_> = flows.iter().collect();
+        flows.sort_by_key(|(f, _)| f.as_str());
+        for (flow, tasks) in flows {
+            pending.push((project.clone(), flow.clone(), tasks.clone()));
+        }
+    }
+    pending.reverse();
+
+    let mut chunks: Vec<Vec<Part>> = vec![];
+    let mut current: Vec<Part> = vec![];
+    while let Some(part) = pending.pop() {
+        current.push(part);
+        let card = render(layout, user_id, &to_details(&current), opts).await;
+        if fits(&card, limits) {
+            continue;
+        }
+
+        let part = current.pop().unwrap_or_default();
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            pending.push(part);
+        } else if part.2.len() > 1 {
+            // a single flow is too big on its own, halve its tasks
+            let (project, flow, mut tasks) = part;
+            let rest = tasks.split_off(tasks.len() / 2);
+            pending.push((project.clone(), flow.clone(), rest));
+            pending.push((project, flow, tasks));
+        } else {
+            println!(
+                "Warning: task {:?} alone exceeds the card limits",
+                part.2[0].job_id
+            );
+            chunks.push(vec![part]);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    let n = chunks.len();
+    let mut cards = vec![];
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut card = render(layout, user_id, &to_details(chunk), opts).await;
+        if n > 1 {
+            let title = &mut card["card"]["header"]["title"]["content"];
+            *title = json!(format!(
+                "{} ({}/{})",
+                title.as_str().unwrap_or_default(),
+                i + 1,
+                n
+            ));
+        }
+        cards.push(card);
+    }
+    cards
+}
+
+/// Jobs that succeeded again after an alert, with who reran them and the failure time.
+pub async fn render_resolved(
+    user_id: &str,
+    resolved: &[(Alert, Recovery)],
+    azkaban_url: Option<&str>,
+) -> Value {
+    let mut frame = template("green", &format!("{} 个任务已恢复", resolved.len())).await;
+    frame["card"]["header"]["title"]["content"] = json!("✅ Scheduled Job Resolved");
+
     let mut elements: Vec<Value> = vec![];
+    if !user_id.is_empty() {
+        elements.push(div_message(&format!("<at id={}></at> 以下任务已恢复:", user_id)).await);
+    }
 
-    // @ username
-    elements.push(div_at_user(user_id).await);
+    let header = ["Job", "Fixed by", "Failed for", "Exec"].map(String::from);
+    elements.push(column_set(&header, true).await);
 
-    for (i, p) in details.into_iter().enumerate() {
-        let project = p.0;
-        let flows = p.1;
+    for (alert, recovery) in resolved {
+        let t = &alert.task;
+        let lasted = (recovery.end_time - alert.first_failed_at)
+            .to_std()
+            .unwrap_or_default();
+        let exec = match azkaban_url {
+            Some(base) => {
+                let mut rerun = t.clone();
+                rerun.exec_id = recovery.exec_id.clone();
+                format!("[{}]({})", recovery.exec_id, execution_url(base, &rerun))
+            }
+            None => recovery.exec_id.clone(),
+        };
+        let row = [
+            format!("{}.{}.{}", t.project_name, t.flow_id, t.job_id),
+            recovery.submit_user.clone(),
+            format_duration_chinese(lasted),
+            exec,
+        ];
+        elements.push(column_set(&row, false).await);
+    }
 
-        elements.push(hr().await);
-        elements.push(div_project(&project).await);
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// What a silence held back, sent when its window is over.
+pub async fn render_silenced(user_id: &str, silence: &Silence, tasks: &[Task]) -> Value {
+    let mut frame = template(
+        "grey",
+        &format!("静默期间 {} 个任务异常: {}", tasks.len(), silence.reason),
+    )
+    .await;
+    frame["card"]["header"]["title"]["content"] = json!("🔕 Silence Ended");
+
+    let mut elements: Vec<Value> = vec![];
+    if !user_id.is_empty() {
+        elements.push(div_at_user(user_id).await);
+    }
+    let window = format!(
+        "{} ~ {}",
+        silence.start.as_deref().unwrap_or("-"),
+        silence.end
+    );
+    elements.push(div_message(&format!("**静默窗口**: {}", window)).await);
+
+    let header = ["Job", "Status", "Attempt", "End"].map(String::from);
+    elements.push(column_set(&header, true).await);
+    for t in tasks {
+        let row = [
+            format!("{}.{}.{}", t.project_name, t.flow_id, t.job_id),
+            status_name(t.status).to_string(),
+            t.attempt.to_string(),
+            t.end_time
+                .with_timezone(&Local)
+                .format("%m-%d %H:%M")
+                .to_string(),
+        ];
+        elements.push(column_set(&row, false).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Weekly list of the flapping jobs of one owner, job key -> (rates, ✅/❌ history).
+pub async fn render_flaky_report(
+    user_id: &str,
+    tasks: &[Task],
+    lines: &HashMap<String, (String, String)>,
+) -> Value {
+    let mut frame = template("purple", &format!("{} 个任务间歇性失败", tasks.len())).await;
+    frame["card"]["header"]["title"]["content"] = json!("🔀 Flaky Job Report");
+
+    let mut elements: Vec<Value> = vec![];
+    if !user_id.is_empty() {
+        elements.push(div_at_user(user_id).await);
+    }
+
+    let header = ["Job", "失败率", "最近"].map(String::from);
+    elements.push(column_set(&header, true).await);
+    for t in tasks {
+        let (rates, spark) = lines.get(&alert_key(t)).cloned().unwrap_or_default();
+        let row = [
+            format!("{}.{}.{}", t.project_name, t.flow_id, t.job_id),
+            rates,
+            spark,
+        ];
+        elements.push(column_set(&row, false).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Weekly list of blamed authors missing from the owner directory, identity -> job keys.
+pub async fn render_unresolved_report(unresolved: &BTreeMap<String, Vec<String>>) -> Value {
+    let mut frame = template(
+        "yellow",
+        &format!("{} 个作者没有对应的负责人", unresolved.len()),
+    )
+    .await;
+    frame["card"]["header"]["title"]["content"] = json!("👤 Unresolved Authors");
+
+    let mut elements: Vec<Value> = vec![];
+    elements.push(div_message("以下作者不在负责人名单中, 请补充映射或别名:").await);
+
+    let header = ["Author", "Jobs", "Examples"].map(String::from);
+    elements.push(column_set(&header, true).await);
+    for (identity, jobs) in unresolved {
+        let mut examples = jobs.iter().take(3).cloned().collect::<Vec<_>>().join(", ");
+        if jobs.len() > 3 {
+            examples.push_str(", ...");
+        }
+        let row = [identity.clone(), jobs.len().to_string(), examples];
+        elements.push(column_set(&row, false).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Failure load of one team over the report window, its most failing jobs first.
+pub async fn render_team_report(lead_id: &str, team: &str, window: &str, load: &Load) -> Value {
+    let mut frame = template(
+        "blue",
+        &format!(
+            "{} 过去 {}: {} 次运行, {} 次失败 ({:.0}%)",
+            team,
+            window,
+            load.runs,
+            load.failures,
+            load.rate() * 100.0
+        ),
+    )
+    .await;
+    frame["card"]["header"]["title"]["content"] = json!("📊 Team Failure Load");
+
+    let mut elements: Vec<Value> = vec![];
+    if !lead_id.is_empty() {
+        elements.push(div_at_user(lead_id).await);
+    }
+
+    let header = ["Job", "失败", "运行"].map(String::from);
+    elements.push(column_set(&header, true).await);
+    for (key, failures, runs) in load.jobs.iter().take(TEAM_REPORT_JOBS) {
+        let row = [key.clone(), failures.to_string(), runs.to_string()];
+        elements.push(column_set(&row, false).await);
+    }
+    if load.jobs.len() > TEAM_REPORT_JOBS {
+        elements.push(
+            div_message(&format!(
+                "... 另有 {} 个任务失败",
+                load.jobs.len() - TEAM_REPORT_JOBS
+            ))
+            .await,
+        );
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Held alerts of one user, counted by project, flow and category.
+pub async fn render_digest(user_id: &str, tasks: &[Task], since: DateTime<Utc>) -> Value {
+    let refs: Vec<&Task> = tasks.iter().collect();
+
+    let mut frame = template(
+        header_template(&refs),
+        &format!(
+            "{} 以来 {} 个任务异常",
+            since.with_timezone(&Local).format("%m-%d %H:%M"),
+            tasks.len()
+        ),
+    )
+    .await;
+    frame["card"]["header"]["title"]["content"] = json!("📋 Scheduled Job Digest");
+
+    let mut elements: Vec<Value> = vec![];
+    if user_id.is_empty() {
+        elements.push(div_message("⚠️ 以下任务没有找到负责人, 请认领处理:").await);
+    } else {
+        elements.push(div_at_user(user_id).await);
+    }
+
+    let mut counts: BTreeMap<(&str, &str, &str), Vec<&str>> = BTreeMap::new();
+    for t in tasks {
+        counts
+            .entry((&t.project_name, &t.flow_id, t.category()))
+            .or_default()
+            .push(&t.job_id);
+    }
+
+    let header = ["Project", "Flow", "Category", "Count"].map(String::from);
+    elements.push(column_set(&header, true).await);
+    for ((project, flow, category), jobs) in &counts {
+        let row = [
+            project.to_string(),
+            flow.to_string(),
+            category.to_string(),
+            jobs.len().to_string(),
+        ];
+        elements.push(column_set(&row, false).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Compact table, one row per flow, for owners with too many tasks for the full card.
+async fn render_summary(user_id: &str, details: &ProjectTasks) -> Value {
+    let all_tasks: Vec<&Task> = details
+        .values()
+        .flat_map(|f| f.values())
+        .flatten()
+        .collect();
+
+    let mut frame = template(
+        header_template(&all_tasks),
+        &format!(
+            "{} 个任务异常, 涉及 {} 个项目",
+            all_tasks.len(),
+            details.len()
+        ),
+    )
+    .await;
+
+    let mut elements: Vec<Value> = vec![];
+    if user_id.is_empty() {
+        elements.push(div_message("⚠️ 以下任务没有找到负责人, 请认领处理:").await);
+    } else {
+        elements.push(div_at_user(user_id).await);
+    }
+
+    let header = ["Project", "Flow", "Count", "Jobs"].map(String::from);
+    elements.push(column_set(&header, true).await);
+
+    let mut rows: Vec<(&String, &String, &Vec<Task>)> = details
+        .iter()
+        .flat_map(|(p, flows)| flows.iter().map(move |(f, t)| (p, f, t)))
+        .collect();
+    rows.sort_by_key(|(p, f, _)| (p.as_str(), f.as_str()));
+
+    for (project, flow, tasks) in rows {
+        let mut jobs: Vec<&str> = tasks.iter().take(5).map(|t| t.job_id.as_str()).collect();
+        if tasks.len() > 5 {
+            jobs.push("…");
+        }
+        let row = [
+            project.clone(),
+            flow.clone(),
+            tasks.len().to_string(),
+            jobs.join(", "),
+        ];
+        elements.push(column_set(&row, false).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
+        arr.extend(elements);
+    }
+
+    frame
+}
+
+/// Why a webhook send failed, `Retryable` ones are worth another try.
+#[derive(Debug)]
+pub enum SendError {
+    Retryable(String),
+    Fatal(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Retryable(m) => write!(f, "retryable: {}", m),
+            SendError::Fatal(m) => write!(f, "fatal: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Feishu bot codes for "too many requests" / "frequency limited"
+const RATE_LIMIT_CODES: [i64; 2] = [9499, 11232];
+
+fn classify(status: StatusCode, code: i64, body: &str) -> SendError {
+    let message = format!("status: {}, body: {}", status, body);
+    if status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || RATE_LIMIT_CODES.contains(&code)
+    {
+        SendError::Retryable(message)
+    } else {
+        SendError::Fatal(message)
+    }
+}
+
+/// Equal jitter: half of the capped exponential delay is fixed, the other half random.
+fn backoff(attempt: u32, base: Duration, max: Duration, jitter: f64) -> Duration {
+    let exp = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    exp / 2 + exp.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+fn is_fatal(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<SendError>(), Some(SendError::Fatal(_)))
+}
+
+/// A failed delivery that sits in the spool, the next run replays it.
+#[derive(Debug)]
+struct Spooled(PathBuf);
+
+impl std::fmt::Display for Spooled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "spooled to {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Spooled {}
+
+/// Whether the failed delivery behind `e` will still go out.
+pub fn is_spooled(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<Spooled>().is_some()
+}
+
+/// Send with retries, a message that still fails is spooled for the next run
+/// unless Feishu refused it for good.
+pub async fn deliver(webhook: &FeishuUrl, payload: Value) -> Result<()> {
+    let retry = &InitConfig::global().retry;
+
+    match send_with_retry(webhook, &payload, retry).await {
+        (_, Ok(_)) => Ok(()),
+        (_, Err(e)) if is_fatal(&e) => Err(anyhow!("Delivery to {} failed: {}", webhook.url(), e)),
+        (attempts, Err(e)) => {
+            let entry = SpoolEntry {
+                url: webhook.url().to_string(),
+                payload,
+                created_at: Utc::now(),
+                attempts,
+                last_error: e.to_string(),
+            };
+            let failed = format!("Delivery to {} failed: {}", webhook.url(), e);
+            match spool::write(Path::new(&retry.spool_dir), &entry).await {
+                Ok(path) => {
+                    println!("Spooled undelivered message to {:?}", path);
+                    Err(anyhow::Error::new(Spooled(path)).context(failed))
+                }
+                Err(se) => {
+                    println!("Error: Can't spool message for {}: {}", entry.url, se);
+                    Err(anyhow!(failed))
+                }
+            }
+        }
+    }
+}
+
+/// The sends made, and how the last one went.
+async fn send_with_retry(webhook: &FeishuUrl, payload: &Value, retry: &Retry) -> (u32, Result<()>) {
+    let mut attempts = 0;
+    let result = try_send(webhook, payload, retry, &mut attempts).await;
+    (attempts, result)
+}
 
-        for f in flows {
-            let flow = f.0;
-            let tasks = f.1;
+async fn try_send(
+    webhook: &FeishuUrl,
+    payload: &Value,
+    retry: &Retry,
+    attempts: &mut u32,
+) -> Result<()> {
+    let base = parse_duration(&retry.base_delay)?;
+    let max = parse_duration(&retry.max_delay)?;
 
-            elements.push(div_flow_and_project(&flow, &project).await);
+    let limits = InitConfig::global()
+        .rate_limits
+        .get(webhook.kind())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
 
-            let x: Vec<String> = tasks.iter().map(|t| t.to_string().unwrap()).collect();
+    loop {
+        ratelimit::acquire(webhook.url(), limits).await?;
 
-            let task_message = x.join("\n");
+        let mut data = payload.clone();
+        if let Some(secret) = webhook.secret() {
+            sign_payload(&mut data, secret)?;
+        }
+
+        let attempt = *attempts;
+        *attempts += 1;
+        match do_send(webhook.url(), data).await {
+            Ok(_) => return Ok(()),
+            Err(SendError::Retryable(m)) if attempt + 1 < retry.max_attempts => {
+                let delay = backoff(attempt, base, max, rand::random::<f64>());
+                println!(
+                    "Retry {} to {} in {:?}, {}",
+                    attempt + 1,
+                    webhook.url(),
+                    delay,
+                    m
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Feishu codes of a webhook that can't post: bad token, bot off, bad signature, keywords
+const WEBHOOK_BROKEN: [(i64, &str); 4] = [
+    (19001, "webhook token invalid"),
+    (19007, "bot is not enabled"),
+    (19021, "signature check failed, check the secret"),
+    (19024, "message lacks the bot's keywords"),
+];
+
+/// Post a message Feishu refuses to show, to see the webhook answers and
+/// takes the token and signature.
+pub async fn probe(webhook: &FeishuUrl) -> Result<String> {
+    let mut payload = json!({"msg_type": "check", "content": {}});
+    if let Some(secret) = webhook.secret() {
+        sign_payload(&mut payload, secret)?;
+    }
+    let response = Client::new()
+        .post(webhook.url())
+        .timeout(Duration::from_secs(10))
+        .json(&payload)
+        .send()
+        .await?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    let code = body["code"].as_i64().unwrap_or(-1);
 
-            let message_detail = div_message(task_message.as_str()).await;
-            elements.push(message_detail);
+    if let Some((_, reason)) = WEBHOOK_BROKEN.iter().find(|(c, _)| *c == code) {
+        return Err(anyhow!("{} ({})", reason, code));
+    }
+    if status.is_server_error() || status == StatusCode::NOT_FOUND {
+        return Err(anyhow!("status {}", status));
+    }
+    Ok(format!("reachable, code {}", code))
+}
+
+/// Resend spooled messages, called once at the start of a run.
+pub async fn replay_spool() -> Result<()> {
+    let config = InitConfig::global();
+    let dir = Path::new(&config.retry.spool_dir);
+    let max_age = parse_duration(&config.retry.spool_max_age)?;
+
+    for (path, mut entry) in spool::load(dir).await? {
+        let age = (Utc::now() - entry.created_at).to_std().unwrap_or_default();
+        if age > max_age {
+            println!("Drop spooled message for {} after {:?}", entry.url, age);
+            spool::remove(&path).await?;
+            continue;
+        }
+
+        let webhook = config
+            .webhook_by_url(&entry.url)
+            .cloned()
+            .unwrap_or_else(|| FeishuUrl::Plain(entry.url.clone()));
+
+        match send_with_retry(&webhook, &entry.payload, &config.retry).await {
+            (_, Ok(_)) => {
+                println!("Replayed spooled message to {}", entry.url);
+                spool::remove(&path).await?;
+            }
+            (_, Err(e)) if is_fatal(&e) => {
+                println!("Drop spooled message for {}: {}", entry.url, e);
+                spool::remove(&path).await?;
+            }
+            (attempts, Err(e)) => {
+                println!("Replay to {} failed again: {}", entry.url, e);
+                entry.attempts += attempts;
+                entry.last_error = e.to_string();
+                spool::rewrite(&path, &entry).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn render_card(
+    user_id: &str,
+    details: &HashMap<String, HashMap<String, Vec<Task>>>,
+    opts: &CardOptions,
+) -> Value {
+    let all_tasks: Vec<&Task> = details
+        .values()
+        .flat_map(|f| f.values())
+        .flatten()
+        .collect();
+    let keys: Vec<String> = all_tasks.iter().map(|t| alert_key(t)).collect();
+    let card = card_id(&keys);
+
+    let mut frame = template(
+        header_template(&all_tasks),
+        &format!(
+            "{} 个任务异常, 涉及 {} 个项目",
+            all_tasks.len(),
+            details.len()
+        ),
+    )
+    .await;
+
+    let mut elements: Vec<Value> = vec![];
+
+    // @ username
+    if user_id.is_empty() {
+        elements.push(div_message("⚠️ 以下任务没有找到负责人, 请认领处理:").await);
+    } else {
+        elements.push(div_at_user(user_id).await);
+    }
+
+    for (project, flows) in details {
+        elements.push(hr().await);
+        elements.push(div_project(project).await);
+
+        for (flow, tasks) in flows {
+            elements.push(div_flow_and_project(flow, project).await);
+
+            let header = ["Job", "Attempt", "Status", "Duration"].map(String::from);
+            elements.push(column_set(&header, true).await);
+
+            for t in tasks {
+                let row = [
+                    t.job_id.clone(),
+                    t.attempt.to_string(),
+                    status_name(t.status).to_string(),
+                    format_duration_chinese(t.duration),
+                ];
+                elements.push(column_set(&row, false).await);
+            }
+
+            for t in tasks {
+                elements.extend(task_extra(t, user_id, &card, opts).await);
+            }
         }
     }
 
-    if let Some(arr) = frame["card"]["elements"].as_array_mut() {
+    if opts.actions && all_tasks.len() > 1 {
+        let value = json!({
+            "action": "ack_all", "card": card,
+            "user_id": user_id, "instance": opts.instance
+        });
+        elements.push(hr().await);
+        elements.push(button_row(vec![callback_button("Ack all", "primary", value).await]).await);
+    }
+
+    if let Some(arr) = frame["card"]["body"]["elements"].as_array_mut() {
         arr.extend(elements);
     }
-    do_send(url, frame).await?;
+
+    frame
+}
+
+/// Description, log excerpt and links of a single task, below the flow table.
+async fn task_extra(task: &Task, user_id: &str, card: &str, opts: &CardOptions) -> Vec<Value> {
+    let mut elements = vec![];
+    let key = alert_key(task);
+
+    for note in opts.notes.get(&key).into_iter().flatten() {
+        elements.push(div_message(&format!("**{}**: {}", task.job_id, note)).await);
+    }
+
+    if let Some(reason) = &task.unowned {
+        let note = format!("**{}**: 未解析到负责人, {}", task.job_id, reason.describe());
+        elements.push(div_message(&note).await);
+    }
+
+    let desc = task.desc.trim();
+    if desc.chars().count() > LONG_DESC_LEN {
+        elements.push(collapsible_panel(&format!("📄 {} 描述", task.job_id), desc).await);
+    } else if !desc.is_empty() {
+        elements.push(div_message(&format!("**{}**: {}", task.job_id, desc)).await);
+    }
+
+    if !task.log_excerpt.trim().is_empty() {
+        let content = format!("```\n{}\n```", task.log_excerpt.trim());
+        elements.push(collapsible_panel(&format!("📜 {} 日志", task.job_id), &content).await);
+    }
+
+    let mut buttons = vec![];
+    if let Some(base) = opts.azkaban_url.as_deref() {
+        let text = format!("执行 {} · {}", task.exec_id, task.job_id);
+        buttons.push(button(&text, &execution_url(base, task)).await);
+        buttons.push(button("日志", &log_url(base, task)).await);
+        buttons.push(button("Flow", &flow_url(base, task)).await);
+    }
+    if let Some(url) = &task.source_url {
+        buttons.push(button("源码", url).await);
+    }
+    if !buttons.is_empty() {
+        elements.push(button_row(buttons).await);
+    }
+
+    if opts.actions {
+        let value = |action: &str| {
+            json!({
+                "action": action, "key": key, "card": card,
+                "user_id": user_id, "instance": opts.instance
+            })
+        };
+        let snooze = format!("Snooze {}", opts.snooze);
+        let mut rerun = callback_button("Rerun flow", "danger", value("rerun")).await;
+        // starts the whole flow again, ask before
+        rerun["confirm"] = json!({
+            "title": {"tag": "plain_text", "content": "重跑 flow?"},
+            "text": {
+                "tag": "plain_text",
+                "content": format!("将重新执行 {}.{}", task.project_name, task.flow_id)
+            }
+        });
+        let actions = vec![
+            callback_button("Ack", "primary", value("ack")).await,
+            callback_button(snooze.trim(), "default", value("snooze")).await,
+            rerun,
+        ];
+        elements.push(button_row(actions).await);
+    }
+
+    elements
+}
+
+fn header_template(tasks: &[&Task]) -> &'static str {
+    if tasks.iter().any(|t| t.severity == Severity::Critical) {
+        "red"
+    } else if !tasks.is_empty() && tasks.iter().all(|t| t.severity == Severity::Info) {
+        "grey"
+    } else if tasks
+        .iter()
+        .any(|t| matches!(status_name(t.status), "FAILED" | "FAILED_FINISHING"))
+    {
+        "red"
+    } else if tasks
+        .iter()
+        .any(|t| matches!(status_name(t.status), "KILLED" | "CANCELLED"))
+    {
+        "orange"
+    } else {
+        "yellow"
+    }
+}
+
+/// Feishu signature: base64(HmacSHA256(key = "{timestamp}\n{secret}", message = "")).
+fn gen_sign(timestamp: i64, secret: &str) -> Result<String> {
+    let string_to_sign = format!("{}\n{}", timestamp, secret);
+    let mac = Hmac::<Sha256>::new_from_slice(string_to_sign.as_bytes())
+        .map_err(|e| anyhow!("Invalid sign key: {}", e))?;
+
+    Ok(STANDARD.encode(mac.finalize().into_bytes()))
+}
+
+fn sign_payload(data: &mut Value, secret: &str) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    data["timestamp"] = json!(timestamp.to_string());
+    data["sign"] = json!(gen_sign(timestamp, secret)?);
 
     Ok(())
 }
 
-async fn do_send(url: &str, data: Value) -> Result<()> {
+async fn do_send(url: &str, data: Value) -> Result<(), SendError> {
     let client = Client::new();
     let response = client
         .post(url)
@@ -62,7 +962,7 @@ async fn do_send(url: &str, data: Value) -> Result<()> {
         .json(&data)
         .send()
         .await
-        .map_err(|e| anyhow!("Request failed: {}", e))?;
+        .map_err(|e| SendError::Retryable(format!("Request failed: {}", e)))?;
 
     let status = &response.status();
 
@@ -93,33 +993,48 @@ async fn do_send(url: &str, data: Value) -> Result<()> {
     };
 
     if !status.is_success() || connected_and_send_status != 0 {
-        return Err(anyhow!(
-            "Request failed, status: {}, body: {}",
-            status,
-            body
-        ));
+        return Err(classify(*status, connected_and_send_status, &body));
     }
 
     Ok(())
 }
 
-async fn template() -> Value {
+/// Put the instance in front of the card title, `[prod] 🚨 ...`.
+pub fn tag_instance(card: &mut Value, instance: Option<&str>) {
+    let Some(name) = instance else {
+        return;
+    };
+    if let Some(title) = card["card"]["header"]["title"].get_mut("content") {
+        *title = Value::from(format!("[{}] {}", name, title.as_str().unwrap_or_default()));
+    }
+}
+
+async fn template(color: &str, subtitle: &str) -> Value {
     json!(
           {
              "msg_type": "interactive",
              "card": {
+                 "schema": "2.0",
                  "config": {
-                     "wide_screen_mode": true,
-                     "enable_forward": false
+                     "update_multi": true,
+                     "enable_forward": false,
+                     "width_mode": "fill"
                  },
-                 "elements": [
-                 ],
                  "header": {
                      "title": {
-                         "content": "🎉 Scheduled Job Fail",
+                         "content": "🚨 Scheduled Job Failed",
+                         "tag": "plain_text"
+                     },
+                     "subtitle": {
+                         "content": subtitle,
                          "tag": "plain_text"
                      },
-                     "template": "blue"
+                     "template": color
+                 },
+                 "body": {
+                     "direction": "vertical",
+                     "elements": [
+                     ]
                  }
              }
          }
@@ -129,11 +1044,8 @@ async fn template() -> Value {
 async fn message_detail(details: Vec<&str>) -> Vec<Value> {
     fn detail(detail: &str) -> Value {
         json!({
-                "tag": "div",
-                "text": {
-                    "content": format!("{}", detail),
-                    "tag": "lark_md"
-                }
+                "tag": "markdown",
+                "content": detail
             }
         )
     }
@@ -150,8 +1062,375 @@ async fn message_detail(details: Vec<&str>) -> Vec<Value> {
 
 #[cfg(test)]
 mod tests {
-    use crate::notice::do_send;
+    use crate::bean::{CardLimits, ProjectTasks};
+    use crate::bean::{Severity, Task, Unowned};
+    use crate::notice::{
+        backoff, build_cards, button_keys, card_id, classify, count_elements, do_send, gen_sign,
+        header_template, is_fatal, render_card, render_digest, render_flaky_report,
+        render_resolved, render_team_report, render_unresolved_report, sign_payload, tag_instance,
+        CardOptions, SendError,
+    };
+    use crate::state::{alert_key, AlertState, Recovery};
+    use crate::team::Load;
+    use reqwest::StatusCode;
     use serde_json::json;
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    #[test]
+    fn test_classify() {
+        let retryable =
+            |status, code| matches!(classify(status, code, ""), SendError::Retryable(_));
+
+        assert!(retryable(StatusCode::BAD_GATEWAY, -1));
+        assert!(retryable(StatusCode::TOO_MANY_REQUESTS, -1));
+        assert!(retryable(StatusCode::OK, 11232));
+        assert!(!retryable(StatusCode::OK, 19021));
+        assert!(!retryable(StatusCode::BAD_REQUEST, 9999));
+
+        // not spooled, as resending can't help
+        assert!(is_fatal(&classify(StatusCode::OK, 19021, "").into()));
+        assert!(!is_fatal(&classify(StatusCode::BAD_GATEWAY, -1, "").into()));
+        assert!(!is_fatal(&anyhow::anyhow!("connection reset")));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(30);
+
+        assert_eq!(backoff(0, base, max, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff(0, base, max, 1.0), Duration::from_secs(1));
+        assert_eq!(backoff(3, base, max, 0.5), Duration::from_secs(6));
+        assert_eq!(backoff(10, base, max, 1.0), max);
+        assert_eq!(backoff(40, base, max, 0.0), Duration::from_secs(15));
+    }
+
+    #[tokio::test]
+    async fn test_render_card() {
+        let mut failed = Task::demo("warehouse", "dwd_a", "ou_demo");
+        failed.log_excerpt = "ERROR boom".to_string();
+        let mut killed = Task::demo("warehouse", "dwd_b", "ou_demo");
+        killed.status = 60;
+        killed.source_url = Some("https://git.example.com/a.flow#L3-9".to_string());
+
+        let mut flows = HashMap::new();
+        flows.insert("daily".to_string(), vec![failed, killed]);
+        let mut details = HashMap::new();
+        details.insert("warehouse".to_string(), flows);
+
+        let opts = CardOptions {
+            azkaban_url: Some("https://az.example.com/".to_string()),
+            actions: true,
+            ..Default::default()
+        };
+        let card = render_card("ou_demo", &details, &opts).await;
+
+        assert_eq!(card["card"]["schema"], "2.0");
+        assert_eq!(card["card"]["header"]["template"], "red");
+
+        let body = card["card"]["body"].to_string();
+        assert!(body.contains("<at id=ou_demo></at>"));
+        assert!(body.contains("collapsible_panel"));
+        assert!(body.contains("https://az.example.com/executor?execid=1001&job=dwd_a"));
+        assert!(body.contains("https://az.example.com/manager?project=warehouse&flow=daily"));
+        assert!(body.contains("https://git.example.com/a.flow#L3-9"));
+        assert!(body.contains("\"action\":\"snooze\"") && body.contains("warehouse.daily.dwd_b"));
+        assert!(body.contains("FAILED") && body.contains("KILLED"));
+    }
+
+    #[test]
+    fn test_header_severity() {
+        let failed = Task::demo("warehouse", "a", "ou_demo");
+        let mut killed = Task::demo("warehouse", "b", "ou_demo");
+        killed.status = 60;
+        assert_eq!(header_template(&[&killed]), "orange");
+
+        killed.severity = Severity::Critical;
+        assert_eq!(header_template(&[&failed, &killed]), "red");
+        killed.severity = Severity::Info;
+        assert_eq!(header_template(&[&killed]), "grey");
+        // one warning is enough to look like a normal failure
+        assert_eq!(header_template(&[&failed, &killed]), "red");
+    }
+
+    #[tokio::test]
+    async fn test_card_buttons() {
+        let mut flows = HashMap::new();
+        flows.insert(
+            "daily".to_string(),
+            vec![
+                Task::demo("warehouse", "dwd_a", "ou_demo"),
+                Task::demo("warehouse", "dwd_b", "ou_demo"),
+            ],
+        );
+        let details = HashMap::from([("warehouse".to_string(), flows)]);
+        let opts = CardOptions {
+            actions: true,
+            ..Default::default()
+        };
+        let card = render_card("ou_demo", &details, &opts).await;
+
+        // each button names its own task and the card, not every task on it
+        let mut cards = HashMap::new();
+        button_keys(&card, &mut cards);
+        let keys = vec![
+            "warehouse.daily.dwd_a".to_string(),
+            "warehouse.daily.dwd_b".to_string(),
+        ];
+        assert_eq!(cards.len(), 1);
+        let mut found = cards[&card_id(&keys)].clone();
+        found.sort();
+        assert_eq!(found, keys);
+
+        let body = card["card"]["body"].to_string();
+        assert!(!body.contains("\"keys\""));
+        assert!(body.contains("\"action\":\"ack_all\""));
+        assert_eq!(body.matches("\"confirm\"").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tag_instance() {
+        let mut details = HashMap::new();
+        details.insert(
+            "warehouse".to_string(),
+            HashMap::from([(
+                "daily".to_string(),
+                vec![Task::demo("warehouse", "a", "ou_demo")],
+            )]),
+        );
+        let opts = CardOptions {
+            actions: true,
+            instance: Some("prod".to_string()),
+            ..Default::default()
+        };
+        let mut card = render_card("ou_demo", &details, &opts).await;
+        tag_instance(&mut card, opts.instance.as_deref());
+
+        assert_eq!(
+            card["card"]["header"]["title"]["content"],
+            "[prod] 🚨 Scheduled Job Failed"
+        );
+        assert!(card["card"]["body"]
+            .to_string()
+            .contains("\"instance\":\"prod\""));
+
+        // a single instance leaves the title alone
+        let mut card = render_card("ou_demo", &details, &CardOptions::default()).await;
+        tag_instance(&mut card, None);
+        assert_eq!(
+            card["card"]["header"]["title"]["content"],
+            "🚨 Scheduled Job Failed"
+        );
+    }
+
+    fn many_tasks(flows: usize, per_flow: usize) -> ProjectTasks {
+        let mut details: ProjectTasks = HashMap::new();
+        for f in 0..flows {
+            for j in 0..per_flow {
+                let mut t = Task::demo("warehouse", &format!("job_{}_{}", f, j), "ou_demo");
+                t.flow_id = format!("flow_{}", f);
+                t.desc = "x".repeat(100);
+                details
+                    .entry("warehouse".to_string())
+                    .or_default()
+                    .entry(t.flow_id.clone())
+                    .or_default()
+                    .push(t);
+            }
+        }
+        details
+    }
+
+    #[tokio::test]
+    async fn test_build_cards_fits() {
+        let details = many_tasks(2, 2);
+        let opts = CardOptions::default();
+        let cards = build_cards("ou_demo", &details, &opts, &CardLimits::default()).await;
+
+        assert_eq!(cards.len(), 1);
+        assert_eq!(
+            cards[0]["card"]["header"]["title"]["content"],
+            "🚨 Scheduled Job Failed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_cards_split() {
+        let details = many_tasks(6, 3);
+        let limits = CardLimits {
+            max_bytes: 8 * 1024,
+            max_elements: 40,
+            summary_threshold: 100,
+        };
+        let opts = CardOptions {
+            azkaban_url: Some("https://az".to_string()),
+            ..Default::default()
+        };
+        let cards = build_cards("ou_demo", &details, &opts, &limits).await;
+
+        assert!(cards.len() > 1);
+        let n = cards.len();
+        for (i, card) in cards.iter().enumerate() {
+            let title = card["card"]["header"]["title"]["content"].as_str().unwrap();
+            assert!(title.ends_with(&format!("({}/{})", i + 1, n)), "{}", title);
+            assert!(serde_json::to_vec(card).unwrap().len() <= limits.max_bytes);
+            assert!(count_elements(&card["card"]["body"]) <= limits.max_elements);
+        }
+
+        let all = cards.iter().map(|c| c.to_string()).collect::<String>();
+        for f in 0..6 {
+            for j in 0..3 {
+                assert!(all.contains(&format!("job_{}_{}", f, j)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_cards_summary() {
+        let details = many_tasks(3, 20);
+        let opts = CardOptions::default();
+        let cards = build_cards("ou_demo", &details, &opts, &CardLimits::default()).await;
+
+        assert_eq!(cards.len(), 1);
+        let body = cards[0]["card"]["body"].to_string();
+        assert!(body.contains("Count") && body.contains("…"));
+        assert!(!body.contains("collapsible_panel"));
+    }
+
+    #[tokio::test]
+    async fn test_render_digest() {
+        let mut tasks = vec![
+            Task::demo("warehouse", "a", "ou_demo"),
+            Task::demo("warehouse", "b", "ou_demo"),
+            Task::demo("ods", "c", "ou_demo"),
+        ];
+        tasks[2].status = 60;
+
+        let card = render_digest("ou_demo", &tasks, chrono::Utc::now()).await;
+
+        let body = card["card"]["body"].to_string();
+        assert_eq!(
+            card["card"]["header"]["title"]["content"],
+            "📋 Scheduled Job Digest"
+        );
+        assert!(body.contains("\"content\":\"2\"") && body.contains("killed"));
+    }
+
+    #[tokio::test]
+    async fn test_render_resolved() {
+        let task = Task::demo("warehouse", "dwd_a", "ou_demo");
+        let mut state = AlertState::default();
+        state.observe(&task, task.end_time);
+        let alert = state.alerts.remove(&alert_key(&task)).unwrap();
+        let recovery = Recovery {
+            exec_id: "2002".to_string(),
+            end_time: task.end_time + chrono::Duration::minutes(90),
+            submit_user: "zhangsan".to_string(),
+        };
+
+        let card = render_resolved("ou_demo", &[(alert, recovery)], Some("https://az")).await;
+
+        let body = card["card"]["body"].to_string();
+        assert_eq!(card["card"]["header"]["template"], "green");
+        assert!(body.contains("zhangsan") && body.contains("1小时30分钟"));
+        assert!(body.contains("https://az/executor?execid=2002&job=dwd_a"));
+    }
+
+    #[tokio::test]
+    async fn test_render_flaky_report() {
+        let task = Task::demo("warehouse", "dwd_a", "ou_demo");
+        let mut lines = HashMap::new();
+        lines.insert(
+            alert_key(&task),
+            ("7d 30% (3/10)".to_string(), "✅❌✅".to_string()),
+        );
+
+        let card = render_flaky_report("ou_demo", &[task], &lines).await;
+
+        let body = card["card"]["body"].to_string();
+        assert_eq!(card["card"]["header"]["template"], "purple");
+        assert!(body.contains("warehouse.daily.dwd_a"));
+        assert!(body.contains("7d 30% (3/10)") && body.contains("✅❌✅"));
+    }
+
+    #[tokio::test]
+    async fn test_render_unresolved_report() {
+        let mut unresolved = BTreeMap::new();
+        unresolved.insert(
+            "lisi <lisi@corp.com>".to_string(),
+            vec!["w.d.a", "w.d.b", "w.d.c", "w.d.e"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        );
+
+        let card = render_unresolved_report(&unresolved).await;
+
+        let body = card["card"]["body"].to_string();
+        assert_eq!(card["card"]["header"]["template"], "yellow");
+        assert!(body.contains("lisi <lisi@corp.com>"));
+        assert!(body.contains("w.d.a, w.d.b, w.d.c, ...") && !body.contains("w.d.e"));
+    }
+
+    #[tokio::test]
+    async fn test_render_team_report() {
+        let load = Load {
+            runs: 40,
+            failures: 12,
+            jobs: (0..12)
+                .map(|i| (format!("dwd.daily.j{:02}", i), 1, 4))
+                .collect(),
+        };
+
+        let card = render_team_report("ou_lead", "warehouse", "7d", &load).await;
+
+        let body = card["card"]["body"].to_string();
+        let header = card["card"]["header"].to_string();
+        assert!(header.contains("warehouse 过去 7d: 40 次运行, 12 次失败 (30%)"));
+        assert!(body.contains("ou_lead") && body.contains("dwd.daily.j09"));
+        assert!(!body.contains("dwd.daily.j10") && body.contains("另有 2 个任务失败"));
+    }
+
+    #[tokio::test]
+    async fn test_render_unowned_card() {
+        let mut t = Task::demo("warehouse", "dwd_a", "");
+        t.unowned = Some(Unowned::MappingMissing("zhangsan".to_string()));
+
+        let mut details = HashMap::new();
+        details
+            .entry("warehouse".to_string())
+            .or_insert_with(HashMap::new)
+            .insert("daily".to_string(), vec![t]);
+
+        let card = render_card("", &details, &CardOptions::default()).await;
+
+        let body = card["card"]["body"].to_string();
+        assert!(!body.contains("<at id="));
+        assert!(body.contains("作者 zhangsan 不在映射文件中"));
+    }
+
+    #[test]
+    fn test_gen_sign() -> anyhow::Result<()> {
+        let sign = gen_sign(1599360473, "demo-secret")?;
+        assert_eq!(sign, "3/MaVZ8JLIy4TUG+7KSFJqvUkTKd+HWY8g+56DZWq8s=");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_payload() -> anyhow::Result<()> {
+        let mut v = json!({"msg_type": "interactive"});
+        sign_payload(&mut v, "demo-secret")?;
+
+        let timestamp: i64 = v["timestamp"].as_str().unwrap().parse()?;
+        assert_eq!(
+            v["sign"].as_str().unwrap(),
+            gen_sign(timestamp, "demo-secret")?
+        );
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn send_demo() -> anyhow::Result<()> {
@@ -0,0 +1,193 @@
+//! on-call rotations as an owner source, `oncall:<rotation>` resolves to
+//! whoever is on call at alert time
+
+use crate::bean::{InitConfig, Rotation, Task};
+use crate::utli::{parse_duration, parse_time};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use std::collections::HashMap;
+
+pub const PREFIX: &str = "oncall:";
+
+/// Member on call at `now`, an override covering `now` wins over the rotation.
+pub fn on_call(rotation: &Rotation, now: DateTime<Utc>) -> Result<String> {
+    for o in &rotation.overrides {
+        if parse_time(&o.start)? <= now && now < parse_time(&o.end)? {
+            return Ok(o.member.clone());
+        }
+    }
+    if rotation.members.is_empty() {
+        return Err(anyhow!("Rotation has no members"));
+    }
+
+    let shift = match rotation.timezone.as_deref() {
+        Some(name) => {
+            let tz: chrono_tz::Tz = name
+                .parse()
+                .map_err(|_| anyhow!("Unknown timezone '{}'", name))?;
+            shift(rotation, &tz, now)?
+        }
+        None => shift(rotation, &Local, now)?,
+    };
+    let i = shift.rem_euclid(rotation.members.len() as i64) as usize;
+    Ok(rotation.members[i].clone())
+}
+
+/// Number of handoffs since the first one, negative before it. Whole day
+/// lengths follow the wall clock so the handoff time survives DST changes.
+fn shift<Tz: TimeZone>(rotation: &Rotation, tz: &Tz, now: DateTime<Utc>) -> Result<i64> {
+    let start = NaiveDate::parse_from_str(rotation.start.trim(), "%Y-%m-%d")?;
+    let handoff = NaiveTime::parse_from_str(rotation.handoff.trim(), "%H:%M")?;
+    let length = Duration::from_std(parse_duration(&rotation.length)?)?;
+    if length <= Duration::zero() {
+        return Err(anyhow!("Bad rotation length '{}'", rotation.length));
+    }
+
+    if length.num_seconds() % 86400 == 0 {
+        let local = now.with_timezone(tz).naive_local();
+        let mut days = (local.date() - start).num_days();
+        if local.time() < handoff {
+            days -= 1;
+        }
+        return Ok(days.div_euclid(length.num_days()));
+    }
+
+    let first = tz
+        .from_local_datetime(&start.and_time(handoff))
+        .earliest()
+        .ok_or_else(|| anyhow!("No local time {} {}", start, handoff))?
+        .with_timezone(&Utc);
+    Ok((now - first).num_seconds().div_euclid(length.num_seconds()))
+}
+
+/// Feishu id for an owner: `oncall:<rotation>`, a name from the mapping file,
+/// or an id as it is.
+pub fn resolve(
+    config: &InitConfig,
+    name_mapping: &HashMap<String, String>,
+    owner: &str,
+    now: DateTime<Utc>,
+) -> Result<String> {
+    let member = match owner.strip_prefix(PREFIX) {
+        Some(name) => {
+            let rotation = config
+                .rotations
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown rotation '{}'", name))?;
+            on_call(rotation, now).map_err(|e| anyhow!("Rotation '{}': {}", name, e))?
+        }
+        None => owner.to_string(),
+    };
+    Ok(name_mapping.get(&member).cloned().unwrap_or(member))
+}
+
+/// Owners set by routes, and on-call project owners for tasks nobody owns.
+pub fn assign(
+    config: &InitConfig,
+    name_mapping: &HashMap<String, String>,
+    tasks: &mut [Task],
+    now: DateTime<Utc>,
+) {
+    for t in tasks.iter_mut() {
+        let owner = config
+            .routes
+            .iter()
+            .filter(|r| r.matcher.matches(t))
+            .find_map(|r| r.assignee.as_deref())
+            .or_else(|| {
+                t.owner
+                    .is_empty()
+                    .then(|| config.fallback.project_owners.get(&t.project_name))
+                    .flatten()
+                    .map(String::as_str)
+                    .filter(|o| o.starts_with(PREFIX))
+            });
+        let Some(owner) = owner else {
+            continue;
+        };
+
+        match resolve(config, name_mapping, owner, now) {
+            Ok(id) => {
+                println!(
+                    "Owner of {}.{}.{} is {} ({})",
+                    t.project_name, t.flow_id, t.job_id, id, owner
+                );
+                t.owner = id;
+                t.unowned = None;
+            }
+            Err(e) => println!("Warning: Can't resolve owner {}: {}", owner, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{InitConfig, Override, Rotation, Task};
+    use crate::oncall::{assign, on_call};
+    use chrono::{Duration, TimeZone, Utc};
+    use std::collections::HashMap;
+
+    fn rotation() -> Rotation {
+        Rotation {
+            members: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            start: "2025-07-28".to_string(),
+            handoff: "10:00".to_string(),
+            length: "7d".to_string(),
+            timezone: Some("Asia/Shanghai".to_string()),
+            overrides: vec![],
+        }
+    }
+
+    #[test]
+    fn test_on_call() {
+        let mut r = rotation();
+        // 10:00 in Shanghai is 02:00 UTC
+        let first = Utc.with_ymd_and_hms(2025, 7, 28, 2, 0, 0).unwrap();
+
+        assert_eq!(on_call(&r, first).unwrap(), "a");
+        assert_eq!(on_call(&r, first - Duration::minutes(1)).unwrap(), "c");
+        assert_eq!(on_call(&r, first + Duration::days(7)).unwrap(), "b");
+        assert_eq!(on_call(&r, first + Duration::days(20)).unwrap(), "c");
+        assert_eq!(on_call(&r, first + Duration::days(21)).unwrap(), "a");
+
+        r.length = "12h".to_string();
+        assert_eq!(on_call(&r, first + Duration::hours(13)).unwrap(), "b");
+
+        r.overrides.push(Override {
+            member: "d".to_string(),
+            start: "2025-07-28T12:00:00+08:00".to_string(),
+            end: "2025-07-28T20:00:00+08:00".to_string(),
+        });
+        assert_eq!(on_call(&r, first + Duration::hours(5)).unwrap(), "d");
+        assert_eq!(on_call(&r, first + Duration::hours(10)).unwrap(), "a");
+
+        r.timezone = Some("Mars/Olympus".to_string());
+        assert!(on_call(&r, first).is_err());
+    }
+
+    #[test]
+    fn test_assign() {
+        let mut config: InitConfig = serde_json::from_value(serde_json::json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "routes": [{"project": "platform", "assignee": "oncall:platform"}],
+            "fallback": {"project_owners": {"warehouse": "oncall:platform"}}
+        }))
+        .unwrap();
+        config.rotations.insert("platform".to_string(), rotation());
+        let mapping = HashMap::from([("a".to_string(), "ou_a".to_string())]);
+        let now = Utc.with_ymd_and_hms(2025, 7, 29, 0, 0, 0).unwrap();
+
+        let mut tasks = vec![
+            Task::demo("platform", "x", "ou_x"),
+            Task::demo("warehouse", "y", ""),
+            Task::demo("warehouse", "z", "ou_z"),
+            Task::demo("report", "w", ""),
+        ];
+        assign(&config, &mapping, &mut tasks, now);
+        let owners: Vec<&str> = tasks.iter().map(|t| t.owner.as_str()).collect();
+        assert_eq!(owners, vec!["ou_a", "ou_a", "ou_z", ""]);
+    }
+}
@@ -2,7 +2,8 @@
 //! so get the final owner
 
 use crate::bean::{InitConfig, Job};
-use crate::gitblame::blame;
+use crate::gitblame::{blame, file_blob_url};
+use crate::link::line_range_url;
 use crate::utli::starts_with_regex;
 use anyhow::Result;
 use futures::future::join_all;
@@ -63,7 +64,9 @@ pub async fn cut_flow_into_each_task(config_path: &PathBuf) -> Result<HashMap<St
                     other: builder.desc.trim().to_string(),
                     flow_file: config_path.clone(),
                     owner: String::new(),
+                    owner_email: String::new(),
                     desc: String::new(), // todo
+                    source_url: None,
                 });
             }
 
@@ -101,13 +104,26 @@ pub async fn cut_flow_into_each_task(config_path: &PathBuf) -> Result<HashMap<St
             other: builder.desc.trim().to_string(),
             flow_file: config_path.clone(),
             owner: String::new(),
+            owner_email: String::new(),
             desc: builder.desc,
+            source_url: None,
         });
     }
 
+    let web_base = InitConfig::global().git_web_url.as_deref();
+    let blob = file_blob_url(config_path, web_base)
+        .await
+        .unwrap_or_else(|e| {
+            println!("Warning: No web url for {:?}: {}", config_path, e);
+            None
+        });
+
     // 获取每个任务的owner
     for job in jobs.iter_mut() {
-        job.owner = blame(job).await.unwrap_or_default();
+        let author = blame(job).await.unwrap_or_default();
+        job.owner = author.name;
+        job.owner_email = author.email;
+        job.source_url = blob.as_ref().map(|b| line_range_url(b, job.start, job.end));
         result
             .entry(filename.clone())
             .or_default()
@@ -117,11 +133,10 @@ pub async fn cut_flow_into_each_task(config_path: &PathBuf) -> Result<HashMap<St
     Ok(result)
 }
 
-pub async fn parse_project_file() -> Result<HashMap<String, HashMap<String, HashMap<String, Job>>>>
-{
+pub async fn parse_project_file(
+    cron_dirs: &[String],
+) -> Result<HashMap<String, HashMap<String, HashMap<String, Job>>>> {
     let mut result: HashMap<String, HashMap<String, HashMap<String, Job>>> = HashMap::new();
-    let config: &InitConfig = InitConfig::global();
-    let cron_dirs = config.target_cron_dir.clone();
     for config_path in cron_dirs {
         let config_path = PathBuf::from(config_path);
         let parse_result = do_parse(&config_path).await?;
@@ -268,7 +283,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_parse() -> Result<()> {
-        let parse = parse_project_file().await?;
+        let parse = parse_project_file(&InitConfig::global().target_cron_dir).await?;
 
         let i = parse.len();
 
@@ -0,0 +1,165 @@
+//! client side sliding windows, one set per webhook url
+
+use crate::bean::RateLimit;
+use crate::utli::parse_duration;
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static LIMITERS: OnceLock<Mutex<HashMap<String, Vec<SlidingWindow>>>> = OnceLock::new();
+
+/// At most `requests` sends in any `per` long span.
+#[derive(Debug)]
+struct SlidingWindow {
+    requests: usize,
+    per: Duration,
+    /// the latest `requests` send times, in the future when sends are queued
+    sent: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    fn new(limit: &RateLimit) -> Result<Self> {
+        Ok(SlidingWindow {
+            requests: limit.requests.max(1) as usize,
+            per: parse_duration(&limit.per)?,
+            sent: VecDeque::new(),
+        })
+    }
+
+    /// Earliest time one more send fits in, once the oldest counted one drops out.
+    fn next_free(&self) -> Option<Instant> {
+        let full = self.sent.len().checked_sub(self.requests)?;
+        Some(self.sent[full] + self.per)
+    }
+
+    fn record(&mut self, at: Instant) {
+        self.sent.push_back(at);
+        if self.sent.len() > self.requests {
+            self.sent.pop_front();
+        }
+    }
+}
+
+fn reserve(
+    limiters: &mut HashMap<String, Vec<SlidingWindow>>,
+    url: &str,
+    limits: &[RateLimit],
+    now: Instant,
+) -> Result<Duration> {
+    if !limiters.contains_key(url) {
+        let windows = limits
+            .iter()
+            .map(SlidingWindow::new)
+            .collect::<Result<Vec<_>>>()?;
+        limiters.insert(url.to_string(), windows);
+    }
+    let Some(windows) = limiters.get_mut(url) else {
+        return Ok(Duration::ZERO);
+    };
+
+    // after every window has room, and not before the sends queued earlier
+    let at = windows
+        .iter()
+        .flat_map(|w| [w.next_free(), w.sent.back().copied()])
+        .flatten()
+        .fold(now, Instant::max);
+    for w in windows.iter_mut() {
+        w.record(at);
+    }
+
+    Ok(at.saturating_duration_since(now))
+}
+
+/// Wait until `url` may be sent to again, sends beyond the limit queue up in call order.
+pub async fn acquire(url: &str, limits: &[RateLimit]) -> Result<()> {
+    let wait = {
+        let mut limiters = LIMITERS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Rate limiter poisoned: {}", e))?;
+        reserve(&mut limiters, url, limits, Instant::now())?
+    };
+
+    if !wait.is_zero() {
+        println!("Rate limit: delaying message to {} by {:?}", url, wait);
+        tokio::time::sleep(wait).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::RateLimit;
+    use crate::ratelimit::reserve;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn limit(requests: u32, per: &str) -> RateLimit {
+        RateLimit {
+            requests,
+            per: per.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_reserve_burst_then_queue() {
+        let mut limiters = HashMap::new();
+        let limits = vec![limit(5, "1s"), limit(100, "1m")];
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            assert_eq!(
+                reserve(&mut limiters, "a", &limits, now).unwrap(),
+                Duration::ZERO
+            );
+        }
+        // 6th and 7th wait for the first sends to leave the per second window
+        let second = Duration::from_secs(1);
+        assert_eq!(reserve(&mut limiters, "a", &limits, now).unwrap(), second);
+        assert_eq!(reserve(&mut limiters, "a", &limits, now).unwrap(), second);
+
+        // other urls have their own windows
+        assert_eq!(
+            reserve(&mut limiters, "b", &limits, now).unwrap(),
+            Duration::ZERO
+        );
+
+        // free again after a while
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            reserve(&mut limiters, "a", &limits, later).unwrap(),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_reserve_per_minute() {
+        let mut limiters = HashMap::new();
+        let limits = vec![limit(5, "1s"), limit(100, "1m")];
+        let start = Instant::now();
+
+        // send as fast as allowed for 5 minutes
+        let mut sent = vec![];
+        let mut now = start;
+        while now < start + Duration::from_secs(300) {
+            now += reserve(&mut limiters, "a", &limits, now).unwrap();
+            sent.push(now);
+        }
+
+        let most_within = |span: Duration| {
+            sent.iter()
+                .map(|&from| {
+                    sent.iter()
+                        .filter(|&&t| t >= from && t < from + span)
+                        .count()
+                })
+                .max()
+                .unwrap_or_default()
+        };
+        assert_eq!(most_within(Duration::from_secs(60)), 100);
+        assert_eq!(most_within(Duration::from_secs(1)), 5);
+        assert!(sent.len() > 500, "sent {}", sent.len());
+    }
+}
@@ -0,0 +1,197 @@
+//! policy driven automatic reruns of failed flows
+
+use crate::azkaban::AzkabanClient;
+use crate::bean::{InitConfig, RerunMode, RerunPolicy, Task};
+use crate::state::{alert_key, Alert, AlertState, Rerun};
+use crate::utli::parse_duration;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// no policy, out of reruns, or already rerun
+    Skip,
+    /// still inside the delay, the alert waits for the rerun
+    Wait,
+    Rerun,
+}
+
+pub fn policy_for<'a>(config: &'a InitConfig, task: &Task) -> Option<&'a RerunPolicy> {
+    config.reruns.iter().find(|p| p.matcher.matches(task))
+}
+
+pub fn decide(
+    policy: &RerunPolicy,
+    alert: Option<&Alert>,
+    task: &Task,
+    now: DateTime<Utc>,
+) -> Result<Decision> {
+    // the flow is still going
+    if matches!(task.category(), "retrying" | "other") {
+        return Ok(Decision::Skip);
+    }
+
+    let reruns = alert.map(|a| a.reruns.as_slice()).unwrap_or_default();
+    if reruns.len() >= policy.max_reruns || reruns.iter().any(|r| r.from_exec_id == task.exec_id) {
+        return Ok(Decision::Skip);
+    }
+
+    let delay = parse_duration(&policy.delay)?;
+    if (now - task.end_time).to_std().unwrap_or_default() < delay {
+        return Ok(Decision::Wait);
+    }
+
+    Ok(Decision::Rerun)
+}
+
+async fn rerun(api: &AzkabanClient, policy: &RerunPolicy, task: &Task) -> Result<String> {
+    let disabled = match policy.mode {
+        RerunMode::Flow => vec![],
+        RerunMode::Failed => api
+            .fetch_exec_flow(&task.exec_id)
+            .await?
+            .nodes
+            .into_iter()
+            .filter(|n| matches!(n.status.as_str(), "SUCCEEDED" | "SKIPPED"))
+            .map(|n| n.id)
+            .collect(),
+    };
+
+    api.execute_flow(&task.project_name, &task.flow_id, &disabled)
+        .await
+}
+
+/// Rerun what the policies allow and record it in the state. Returns the tasks to
+/// alert now, the ones waiting for their rerun delay are left out.
+pub async fn remediate(
+    config: &InitConfig,
+    api: Option<&AzkabanClient>,
+    state: &mut AlertState,
+    tasks: Vec<Task>,
+    now: DateTime<Utc>,
+) -> Vec<Task> {
+    if config.reruns.is_empty() {
+        return tasks;
+    }
+    let Some(api) = api else {
+        println!("Warning: reruns are configured but azkaban_api is not");
+        return tasks;
+    };
+
+    // several jobs of one execution fail together, rerun it once
+    let mut started: HashMap<String, String> = HashMap::new();
+    let mut result = vec![];
+
+    for task in tasks {
+        let Some(policy) = policy_for(config, &task) else {
+            result.push(task);
+            continue;
+        };
+        let key = alert_key(&task);
+
+        let decision = if started.contains_key(&task.exec_id) {
+            Decision::Rerun
+        } else {
+            decide(policy, state.alerts.get(&key), &task, now).unwrap_or_else(|e| {
+                println!("Warning: Bad rerun policy for {}: {}", key, e);
+                Decision::Skip
+            })
+        };
+
+        match decision {
+            Decision::Skip => {}
+            Decision::Wait => {
+                println!("Waiting {} before rerunning {}", policy.delay, key);
+                continue;
+            }
+            Decision::Rerun => {
+                let exec_id = match started.get(&task.exec_id) {
+                    Some(id) => Ok(id.clone()),
+                    None => rerun(api, policy, &task).await,
+                };
+                match exec_id {
+                    Ok(exec_id) => {
+                        println!("Reran {} of {} as {}", task.flow_id, task.exec_id, exec_id);
+                        started.insert(task.exec_id.clone(), exec_id.clone());
+                        if let Some(alert) = state.alerts.get_mut(&key) {
+                            alert.reruns.push(Rerun {
+                                from_exec_id: task.exec_id.clone(),
+                                exec_id,
+                                at: now,
+                            });
+                        }
+                    }
+                    Err(e) => println!("Error: Can't rerun {}: {}", key, e),
+                }
+            }
+        }
+
+        result.push(task);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{Matcher, RerunMode, RerunPolicy, Task};
+    use crate::remediate::{decide, Decision};
+    use crate::state::{alert_key, AlertState, Rerun};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_decide() {
+        let policy = RerunPolicy {
+            matcher: Matcher::default(),
+            max_reruns: 2,
+            delay: "10m".to_string(),
+            mode: RerunMode::Failed,
+        };
+        let now = Utc::now();
+        let mut task = Task::demo("warehouse", "a", "ou_a");
+        task.end_time = now - Duration::minutes(5);
+
+        assert_eq!(decide(&policy, None, &task, now).unwrap(), Decision::Wait);
+        task.end_time = now - Duration::minutes(15);
+        assert_eq!(decide(&policy, None, &task, now).unwrap(), Decision::Rerun);
+
+        let mut state = AlertState::default();
+        state.observe(&task, now);
+        let alert = state.alerts.get_mut(&alert_key(&task)).unwrap();
+        alert.reruns.push(Rerun {
+            from_exec_id: task.exec_id.clone(),
+            exec_id: "1002".to_string(),
+            at: now,
+        });
+        // rerun already started for this execution
+        assert_eq!(
+            decide(&policy, Some(alert), &task, now).unwrap(),
+            Decision::Skip
+        );
+
+        // the rerun failed as well, one more to go
+        task.exec_id = "1002".to_string();
+        assert_eq!(
+            decide(&policy, Some(alert), &task, now).unwrap(),
+            Decision::Rerun
+        );
+        assert!(alert.notes(None)[0].contains("已第 1 次自动重跑"));
+
+        alert.reruns.push(Rerun {
+            from_exec_id: "1002".to_string(),
+            exec_id: "1003".to_string(),
+            at: now,
+        });
+        task.exec_id = "1003".to_string();
+        assert_eq!(
+            decide(&policy, Some(alert), &task, now).unwrap(),
+            Decision::Skip
+        );
+
+        // still retrying inside the flow
+        task.status = 30;
+        task.attempt = 1;
+        assert_eq!(decide(&policy, None, &task, now).unwrap(), Decision::Skip);
+    }
+}
@@ -0,0 +1,360 @@
+//! decide which webhook each task goes to and who gets the @
+
+use crate::bean::{Fallback, FeishuUrl, InitConfig, ProjectTasks, Task, Unowned};
+use crate::team;
+use std::collections::HashMap;
+
+/// One card: the tasks of one user (empty for nobody) sent to one webhook.
+#[derive(Debug)]
+pub struct Delivery {
+    pub webhook: FeishuUrl,
+    pub user_id: String,
+    pub projects: ProjectTasks,
+}
+
+/// Give unowned tasks the project default owner, the reason is kept for the card.
+fn fallback_owner(fallback: &Fallback, task: &mut Task) {
+    if !task.owner.is_empty() {
+        return;
+    }
+    if let Some(owner) = fallback.project_owners.get(&task.project_name) {
+        task.owner = owner.clone();
+    }
+}
+
+fn fallback_channel<'a>(fallback: &'a Fallback, project: &str) -> Option<&'a FeishuUrl> {
+    fallback
+        .team_channels
+        .get(project)
+        .or(fallback.unowned_channel.as_ref())
+}
+
+/// Channels of the matching routes, `None` when no route with channels matched.
+fn routed_channels<'a>(config: &'a InitConfig, task: &Task) -> Option<Vec<&'a FeishuUrl>> {
+    let mut matched = false;
+    let mut channels: Vec<&FeishuUrl> = vec![];
+
+    // assignee-only routes are for `oncall::assign`, not for the destination
+    let routes = config.routes.iter().filter(|r| !r.channels.is_empty());
+    for route in routes.filter(|r| r.matcher.matches(task)) {
+        matched = true;
+        for name in &route.channels {
+            match config.channels.get(name) {
+                Some(c) if !channels.iter().any(|e| e.url() == c.url()) => channels.push(c),
+                Some(_) => {}
+                None => println!("Warning: route refers to unknown channel '{}'", name),
+            }
+        }
+        if !route.continue_matching {
+            break;
+        }
+    }
+
+    matched.then_some(channels)
+}
+
+fn destinations<'a>(config: &'a InitConfig, task: &Task) -> Vec<&'a FeishuUrl> {
+    if let Some(channels) = routed_channels(config, task) {
+        channels
+    } else if let Some(c) = task.team.as_deref().and_then(|t| team::channel(config, t)) {
+        vec![c]
+    } else if task.owner.is_empty() {
+        fallback_channel(&config.fallback, &task.project_name)
+            .into_iter()
+            .collect()
+    } else {
+        config.feishu_url.iter().collect()
+    }
+}
+
+pub fn route(config: &InitConfig, tasks: Vec<Task>) -> Vec<Delivery> {
+    let mut deliveries: Vec<Delivery> = vec![];
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+
+    for mut t in tasks {
+        fallback_owner(&config.fallback, &mut t);
+
+        let webhooks = destinations(config, &t);
+        if webhooks.is_empty() {
+            println!(
+                "No channel to send to, jump task {}.{}.{} ({})",
+                t.project_name,
+                t.flow_id,
+                t.job_id,
+                t.unowned
+                    .as_ref()
+                    .map(Unowned::describe)
+                    .unwrap_or_default()
+            );
+            continue;
+        }
+
+        for webhook in webhooks {
+            let key = (webhook.url().to_string(), t.owner.clone());
+            let i = *index.entry(key).or_insert_with(|| {
+                deliveries.push(Delivery {
+                    webhook: webhook.clone(),
+                    user_id: t.owner.clone(),
+                    projects: HashMap::new(),
+                });
+                deliveries.len() - 1
+            });
+
+            deliveries[i]
+                .projects
+                .entry(t.project_name.clone())
+                .or_default()
+                .entry(t.flow_id.clone())
+                .or_default()
+                .push(t.clone());
+        }
+    }
+
+    deliveries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{FeishuUrl, InitConfig, Task, Unowned};
+    use crate::oncall;
+    use crate::route::route;
+    use crate::severity;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn task(project: &str, owner: &str) -> Task {
+        let mut t = Task::demo(project, "job", owner);
+        if owner.is_empty() {
+            t.unowned = Some(Unowned::BlameEmpty);
+        }
+        t
+    }
+
+    fn config() -> InitConfig {
+        serde_json::from_value(serde_json::json!({
+            "feishu_url": ["https://hook/all"],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "fallback": {
+                "project_owners": {"ods": "ou_ods"},
+                "team_channels": {"dwd": "https://hook/dwd"},
+                "unowned_channel": {"url": "https://hook/unowned", "secret": "s"}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route_fallback_chain() {
+        let tasks = vec![
+            task("ods", "ou_a"),
+            task("ods", ""),
+            task("dwd", ""),
+            task("ads", ""),
+        ];
+        let deliveries = route(&config(), tasks);
+
+        let find = |url: &str, user: &str| {
+            deliveries
+                .iter()
+                .any(|d| d.webhook.url() == url && d.user_id == user)
+        };
+
+        assert_eq!(deliveries.len(), 4);
+        assert!(find("https://hook/all", "ou_a"));
+        assert!(find("https://hook/all", "ou_ods"));
+        assert!(find("https://hook/dwd", ""));
+        assert!(find("https://hook/unowned", ""));
+    }
+
+    #[test]
+    fn test_route_drop_without_fallback() {
+        let mut config = config();
+        config.fallback.unowned_channel = None;
+
+        let deliveries = route(&config, vec![task("ads", "")]);
+        assert!(deliveries.is_empty());
+
+        config.feishu_url = vec![FeishuUrl::Plain("https://hook/other".to_string())];
+        let deliveries = route(&config, vec![task("ads", "ou_b")]);
+        assert_eq!(deliveries[0].webhook.url(), "https://hook/other");
+    }
+
+    #[test]
+    fn test_route_rules() {
+        let mut config = config();
+        config.channels = serde_json::from_value(serde_json::json!({
+            "dwd": "https://hook/dwd-team",
+            "kill": "https://hook/killed",
+            "vip": "https://hook/vip"
+        }))
+        .unwrap();
+        config.routes = serde_json::from_value(serde_json::json!([
+            {"owner": "ou_boss", "channels": ["vip"], "continue": true},
+            {"project": "dwd_*", "category": "failed", "channels": ["dwd"]},
+            {"category": "killed", "channels": ["kill", "missing"]}
+        ]))
+        .unwrap();
+
+        let urls = |t: Task| -> Vec<String> {
+            route(&config, vec![t])
+                .iter()
+                .map(|d| d.webhook.url().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            urls(task("dwd_bill", "ou_a")),
+            vec!["https://hook/dwd-team"]
+        );
+        assert_eq!(
+            urls(task("dwd_bill", "ou_boss")),
+            vec!["https://hook/vip", "https://hook/dwd-team"]
+        );
+
+        let mut killed = task("ods", "ou_a");
+        killed.status = 60;
+        assert_eq!(urls(killed), vec!["https://hook/killed"]);
+
+        // no route matched, default
+        assert_eq!(urls(task("ods", "ou_a")), vec!["https://hook/all"]);
+        // no route matched and unowned, fallback
+        assert_eq!(urls(task("dwd", "")), vec!["https://hook/dwd"]);
+    }
+
+    #[test]
+    fn test_route_assignee_only() {
+        let mut config = config();
+        config.channels = serde_json::from_value(serde_json::json!({
+            "kill": "https://hook/killed"
+        }))
+        .unwrap();
+        config.routes = serde_json::from_value(serde_json::json!([
+            {"project": "ads", "assignee": "oncall:platform"},
+            {"category": "killed", "channels": ["kill"]}
+        ]))
+        .unwrap();
+
+        let urls = |t: Task| -> Vec<String> {
+            route(&config, vec![t])
+                .iter()
+                .map(|d| d.webhook.url().to_string())
+                .collect()
+        };
+
+        assert_eq!(urls(task("ads", "ou_a")), vec!["https://hook/all"]);
+        assert_eq!(urls(task("ads", "")), vec!["https://hook/unowned"]);
+        let mut killed = task("ads", "ou_a");
+        killed.status = 60;
+        assert_eq!(urls(killed), vec!["https://hook/killed"]);
+    }
+
+    #[test]
+    fn test_route_team() {
+        let mut config = config();
+        config.channels = serde_json::from_value(serde_json::json!({
+            "platform": "https://hook/platform",
+            "oncall": "https://hook/oncall"
+        }))
+        .unwrap();
+        config.teams = serde_json::from_value(serde_json::json!({
+            "platform": {"channel": "platform"}
+        }))
+        .unwrap();
+        config.routes = serde_json::from_value(serde_json::json!([
+            {"team": "platform", "category": "killed", "channels": ["oncall"]}
+        ]))
+        .unwrap();
+
+        let urls = |t: Task| -> Vec<String> {
+            route(&config, vec![t])
+                .iter()
+                .map(|d| d.webhook.url().to_string())
+                .collect()
+        };
+
+        let mut t = task("ods", "ou_a");
+        t.team = Some("platform".to_string());
+        assert_eq!(urls(t.clone()), vec!["https://hook/platform"]);
+        t.owner = String::new();
+        assert_eq!(urls(t.clone()), vec!["https://hook/platform"]);
+        t.status = 60;
+        assert_eq!(urls(t), vec!["https://hook/oncall"]);
+    }
+
+    #[test]
+    fn test_route_instance() {
+        let mut config = config();
+        config.channels = serde_json::from_value(serde_json::json!({
+            "prod-dwd": "https://hook/prod-dwd",
+            "test": "https://hook/test"
+        }))
+        .unwrap();
+        config.routes = serde_json::from_value(serde_json::json!([
+            {"instance": "test", "channels": ["test"]}
+        ]))
+        .unwrap();
+        config.instances = serde_json::from_value(serde_json::json!([
+            {"name": "prod", "feishu_url": ["https://hook/prod"],
+             "routes": [{"project": "dwd_*", "channels": ["prod-dwd"]}]},
+            {"name": "test", "db_full_url": "mysql://test"}
+        ]))
+        .unwrap();
+
+        let prod = config.for_instance(&config.instances[0]);
+        let test = config.for_instance(&config.instances[1]);
+        assert_eq!(prod.db_full_url, config.db_full_url);
+        assert_eq!(test.db_full_url, "mysql://test");
+        assert_eq!(prod.state.path, "alert_state.prod.json");
+        assert_eq!(test.digest.store, "digest.test.json");
+
+        let urls = |config: &InitConfig, instance: &str, project: &str| -> Vec<String> {
+            let mut t = task(project, "ou_a");
+            t.instance = Some(instance.to_string());
+            route(config, vec![t])
+                .iter()
+                .map(|d| d.webhook.url().to_string())
+                .collect()
+        };
+        assert_eq!(
+            urls(&prod, "prod", "dwd_bill"),
+            vec!["https://hook/prod-dwd"]
+        );
+        assert_eq!(urls(&prod, "prod", "ods"), vec!["https://hook/prod"]);
+        assert_eq!(urls(&test, "test", "dwd_bill"), vec!["https://hook/test"]);
+    }
+
+    #[test]
+    fn test_route_severity() {
+        let mut config = config();
+        config.channels = serde_json::from_value(serde_json::json!({
+            "pager": "https://hook/pager"
+        }))
+        .unwrap();
+        config.severities = serde_json::from_value(serde_json::json!([
+            {"project": "ads_*", "severity": "critical"}
+        ]))
+        .unwrap();
+        config.routes = serde_json::from_value(serde_json::json!([
+            {"severity": "critical", "channels": ["pager"], "assignee": "ou_pager"}
+        ]))
+        .unwrap();
+
+        let mut tasks = vec![task("ads_bill", "ou_a"), task("ods", "ou_a")];
+        severity::assign(&config, &mut tasks);
+        oncall::assign(&config, &HashMap::new(), &mut tasks, Utc::now());
+
+        let deliveries: Vec<(String, String)> = route(&config, tasks)
+            .iter()
+            .map(|d| (d.webhook.url().to_string(), d.user_id.clone()))
+            .collect();
+        assert_eq!(
+            deliveries,
+            vec![
+                ("https://hook/pager".to_string(), "ou_pager".to_string()),
+                ("https://hook/all".to_string(), "ou_a".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! how bad a failure is, by rules over instance, project, flow, job and category
+
+use crate::bean::{InitConfig, Severity, Task};
+
+pub fn severity_of(config: &InitConfig, task: &Task) -> Severity {
+    config
+        .severities
+        .iter()
+        .find(|r| r.matcher.matches(task))
+        .map(|r| r.severity)
+        .unwrap_or_default()
+}
+
+/// Severity of every task, before routes and silences look at it.
+pub fn assign(config: &InitConfig, tasks: &mut [Task]) {
+    for t in tasks.iter_mut() {
+        t.severity = severity_of(config, t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{InitConfig, Severity, Task};
+    use crate::severity::assign;
+
+    #[test]
+    fn test_assign() {
+        let config: InitConfig = serde_json::from_value(serde_json::json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "severities": [
+                {"project": "*_test", "severity": "info"},
+                {"instance": "test", "severity": "info"},
+                {"project": "ads_*", "category": "failed", "severity": "critical"},
+                {"job": "*_report", "severity": "critical"}
+            ]
+        }))
+        .unwrap();
+
+        let mut on_test = Task::demo("ads_bill", "a", "ou_a");
+        on_test.instance = Some("test".to_string());
+        let mut killed = Task::demo("ads_bill", "b", "ou_a");
+        killed.status = 60;
+        let mut tasks = vec![
+            Task::demo("warehouse_test", "daily_report", "ou_a"),
+            on_test,
+            Task::demo("ads_bill", "c", "ou_a"),
+            killed,
+            Task::demo("dwd", "daily_report", "ou_a"),
+            Task::demo("dwd", "d", "ou_a"),
+        ];
+        assign(&config, &mut tasks);
+
+        let severities: Vec<Severity> = tasks.iter().map(|t| t.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::Info,
+                Severity::Info,
+                Severity::Critical,
+                Severity::Warning,
+                Severity::Critical,
+                Severity::Warning
+            ]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! maintenance windows: matching alerts are recorded instead of sent, and
+//! summarized once the window is over
+
+use crate::bean::{InitConfig, Matcher, Silence, Task};
+use crate::utli::{lock_file, parse_duration, parse_time, write_atomic};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SilenceStore {
+    /// added from the cli / api
+    #[serde(default)]
+    pub silences: Vec<Silence>,
+    /// silence id -> alerts it held back, of every instance
+    #[serde(default)]
+    pub suppressed: HashMap<String, Vec<Task>>,
+}
+
+impl Silence {
+    /// Config silences without an id are told apart by their window and reason.
+    pub fn key(&self) -> String {
+        match &self.id {
+            Some(id) => id.clone(),
+            None => format!(
+                "{}~{} {}",
+                self.start.as_deref().unwrap_or_default(),
+                self.end,
+                self.reason
+            ),
+        }
+    }
+
+    pub fn window(&self) -> Result<(Option<DateTime<Utc>>, DateTime<Utc>)> {
+        let start = self.start.as_deref().map(parse_time).transpose()?;
+        Ok((start, parse_time(&self.end)?))
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        match self.window() {
+            Ok((start, end)) => start.is_none_or(|s| s <= now) && now < end,
+            Err(_) => false,
+        }
+    }
+
+    pub fn ended(&self, now: DateTime<Utc>) -> bool {
+        self.window().is_ok_and(|(_, end)| end <= now)
+    }
+}
+
+pub async fn load(path: &Path) -> Result<SilenceStore> {
+    if !path.exists() {
+        return Ok(SilenceStore::default());
+    }
+    let content = fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub async fn save(path: &Path, store: &SilenceStore) -> Result<()> {
+    write_atomic(path, serde_json::to_vec_pretty(store)?).await
+}
+
+/// Silences from the config followed by the stored ones.
+pub fn all(config: &InitConfig, store: &SilenceStore) -> Vec<Silence> {
+    let mut silences = config.silences.clone();
+    silences.extend(store.silences.iter().cloned());
+    silences
+}
+
+/// Record the tasks under an active silence, returns the rest.
+pub fn suppress(
+    silences: &[Silence],
+    store: &mut SilenceStore,
+    tasks: Vec<Task>,
+    now: DateTime<Utc>,
+) -> Vec<Task> {
+    let mut result = vec![];
+
+    for task in tasks {
+        let Some(silence) = silences
+            .iter()
+            .find(|s| s.active(now) && s.matcher.matches(&task))
+        else {
+            result.push(task);
+            continue;
+        };
+
+        println!(
+            "Silenced {}.{}.{} ({})",
+            task.project_name, task.flow_id, task.job_id, silence.reason
+        );
+        let held = store.suppressed.entry(silence.key()).or_default();
+        match held.iter_mut().find(|h| {
+            h.instance == task.instance
+                && h.project_name == task.project_name
+                && h.flow_id == task.flow_id
+                && h.job_id == task.job_id
+        }) {
+            Some(h) => *h = task,
+            None => held.push(task),
+        }
+    }
+
+    result
+}
+
+/// Silences that are over, with what they held back on `instance`. Those
+/// records are dropped from the store, the stored silences once no instance
+/// has any left.
+pub fn ended(
+    silences: &[Silence],
+    store: &mut SilenceStore,
+    instance: Option<&str>,
+    now: DateTime<Utc>,
+) -> Vec<(Silence, Vec<Task>)> {
+    let mut result = vec![];
+    for s in silences.iter().filter(|s| s.ended(now)) {
+        let Some(held) = store.suppressed.get_mut(&s.key()) else {
+            continue;
+        };
+        let (tasks, others): (Vec<Task>, Vec<Task>) = held
+            .drain(..)
+            .partition(|t| t.instance.as_deref() == instance);
+        *held = others;
+        if held.is_empty() {
+            store.suppressed.remove(&s.key());
+        }
+        if !tasks.is_empty() {
+            result.push((s.clone(), tasks));
+        }
+    }
+    prune(store, now);
+    result
+}
+
+/// Drop the stored silences that are over and hold nothing back any more.
+pub fn prune(store: &mut SilenceStore, now: DateTime<Utc>) {
+    store
+        .silences
+        .retain(|s| !s.ended(now) || store.suppressed.contains_key(&s.key()));
+}
+
+/// `azmonitor silence list | add ... | remove <id>`
+pub async fn cli(args: &[String]) -> Result<()> {
+    let config = InitConfig::global();
+    let path = Path::new(&config.silence_store);
+    let _lock = lock_file(path).await?;
+    let mut store = load(path).await?;
+
+    match args.first().map(String::as_str) {
+        Some("list") | None => {
+            let now = Utc::now();
+            for s in all(config, &store) {
+                let state = if s.active(now) {
+                    "active"
+                } else if s.ended(now) {
+                    "ended"
+                } else {
+                    "pending"
+                };
+                println!(
+                    "{}\t{}\t{} ~ {}\t{}",
+                    s.key(),
+                    state,
+                    s.start.as_deref().unwrap_or("-"),
+                    s.end,
+                    s.reason
+                );
+            }
+        }
+        Some("add") => {
+            let silence = parse_add(&args[1..], Utc::now())?;
+            println!("Added silence {}", silence.key());
+            store.silences.push(silence);
+            save(path, &store).await?;
+        }
+        Some("remove") => {
+            let id = args
+                .get(1)
+                .ok_or_else(|| anyhow!("Usage: silence remove <id>"))?;
+            let before = store.silences.len();
+            store.silences.retain(|s| s.id.as_deref() != Some(id));
+            if store.silences.len() == before {
+                return Err(anyhow!("No stored silence {}", id));
+            }
+            save(path, &store).await?;
+            println!("Removed silence {}", id);
+        }
+        Some(other) => return Err(anyhow!("Unknown silence command {}", other)),
+    }
+
+    Ok(())
+}
+
+/// `--end` is a time or a duration from `--start` (or now), like `2h`.
+fn parse_add(args: &[String], now: DateTime<Utc>) -> Result<Silence> {
+    let mut opts: HashMap<&str, &str> = HashMap::new();
+    let mut it = args.iter();
+    while let Some(flag) = it.next() {
+        let name = flag
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("Unexpected argument {}", flag))?;
+        let value = it
+            .next()
+            .ok_or_else(|| anyhow!("--{} needs a value", name))?;
+        opts.insert(name, value);
+    }
+
+    let get = |name: &str| opts.get(name).map(|v| v.to_string());
+    let silence = Silence {
+        id: None,
+        matcher: Matcher {
+            project: get("project"),
+            flow: get("flow"),
+            job: get("job"),
+            owner: get("owner"),
+            category: get("category"),
+            team: get("team"),
+            instance: get("instance"),
+            severity: get("severity"),
+        },
+        start: get("start"),
+        end: get("end").ok_or_else(|| anyhow!("--end is required"))?,
+        reason: get("reason").ok_or_else(|| anyhow!("--reason is required"))?,
+        created_by: get("by"),
+    };
+    normalize(silence, now)
+}
+
+/// Give a new silence an id and absolute times, rejects a window that is already over.
+pub fn normalize(mut silence: Silence, now: DateTime<Utc>) -> Result<Silence> {
+    let start = silence.start.as_deref().map(parse_time).transpose()?;
+    let end = match parse_time(&silence.end) {
+        Ok(end) => end,
+        Err(_) => {
+            let length = parse_duration(&silence.end)?;
+            start.unwrap_or(now) + chrono::Duration::from_std(length)?
+        }
+    };
+    if end <= now || start.is_some_and(|s| s >= end) {
+        return Err(anyhow!("Silence window is empty or already over"));
+    }
+
+    silence.id = Some(format!("{:08x}", rand::random::<u32>()));
+    silence.start = start.map(|s| s.with_timezone(&Local).to_rfc3339());
+    silence.end = end.with_timezone(&Local).to_rfc3339();
+    Ok(silence)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{Matcher, Silence, Task};
+    use crate::silence::{ended, parse_add, suppress, SilenceStore};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_suppress_and_end() {
+        let now = Utc::now();
+        let silence = Silence {
+            id: Some("s1".to_string()),
+            matcher: Matcher {
+                project: Some("warehouse".to_string()),
+                ..Default::default()
+            },
+            start: Some((now - Duration::hours(1)).to_rfc3339()),
+            end: (now + Duration::hours(1)).to_rfc3339(),
+            reason: "cluster upgrade".to_string(),
+            created_by: None,
+        };
+        let silences = vec![silence];
+        let mut store = SilenceStore::default();
+
+        let tasks = vec![
+            Task::demo("warehouse", "a", "ou_a"),
+            Task::demo("report", "b", "ou_b"),
+        ];
+        let rest = suppress(&silences, &mut store, tasks.clone(), now);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].project_name, "report");
+
+        // the same job again is recorded once
+        suppress(&silences, &mut store, tasks, now);
+        assert_eq!(store.suppressed["s1"].len(), 1);
+
+        assert!(ended(&silences, &mut store, None, now).is_empty());
+        let later = now + Duration::hours(2);
+        let summary = ended(&silences, &mut store, None, later);
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].0.reason, "cluster upgrade");
+        assert!(store.suppressed.is_empty());
+
+        // nothing is silenced after the window
+        let rest = suppress(
+            &silences,
+            &mut store,
+            vec![Task::demo("warehouse", "a", "ou_a")],
+            later,
+        );
+        assert_eq!(rest.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_add() {
+        let now = Utc::now();
+        let args: Vec<String> = [
+            "--project",
+            "warehouse",
+            "--end",
+            "2h",
+            "--reason",
+            "backfill",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let silence = parse_add(&args, now).unwrap();
+        assert!(silence.id.is_some());
+        assert!(silence.active(now));
+        assert!(silence.ended(now + Duration::hours(3)));
+
+        let args: Vec<String> = ["--end", "2000-01-01 00:00", "--reason", "x"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_add(&args, now).is_err());
+        assert!(parse_add(&["--end".to_string()], now).is_err());
+    }
+
+    #[test]
+    fn test_end_per_instance() {
+        let now = Utc::now();
+        let silence = Silence {
+            id: Some("s1".to_string()),
+            end: (now + Duration::hours(1)).to_rfc3339(),
+            reason: "network maintenance".to_string(),
+            ..Default::default()
+        };
+        let silences = vec![silence];
+        let mut store = SilenceStore {
+            silences: silences.clone(),
+            ..Default::default()
+        };
+        let tasks: Vec<Task> = ["prod", "test"]
+            .iter()
+            .map(|i| {
+                let mut t = Task::demo("warehouse", "a", "ou_a");
+                t.instance = Some(i.to_string());
+                t
+            })
+            .collect();
+        suppress(&silences, &mut store, tasks, now);
+        assert_eq!(store.suppressed["s1"].len(), 2);
+
+        // each instance sums up its own alerts, the silence stays until both did
+        let later = now + Duration::hours(2);
+        let summary = ended(&silences, &mut store, Some("prod"), later);
+        assert_eq!(summary[0].1.len(), 1);
+        assert_eq!(summary[0].1[0].instance.as_deref(), Some("prod"));
+        assert_eq!(store.silences.len(), 1);
+
+        let summary = ended(&silences, &mut store, Some("test"), later);
+        assert_eq!(summary[0].1[0].instance.as_deref(), Some("test"));
+        assert!(store.suppressed.is_empty());
+        assert!(store.silences.is_empty());
+    }
+}
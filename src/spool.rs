@@ -0,0 +1,111 @@
+//! dead-letter spool for webhook messages that could not be delivered
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolEntry {
+    /// the secret is looked up from the config again on replay
+    pub url: String,
+    /// unsigned payload, the signature has to be fresh on every send
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+pub async fn write(dir: &Path, entry: &SpoolEntry) -> Result<PathBuf> {
+    fs::create_dir_all(dir).await?;
+
+    let name = format!(
+        "{}-{:08x}.json",
+        entry.created_at.timestamp_millis(),
+        rand::random::<u32>()
+    );
+    let path = dir.join(name);
+
+    fs::write(&path, serde_json::to_vec_pretty(entry)?).await?;
+
+    Ok(path)
+}
+
+pub async fn rewrite(path: &Path, entry: &SpoolEntry) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(entry)?).await?;
+    Ok(())
+}
+
+/// All entries, oldest first. Unreadable files are reported and left alone.
+pub async fn load(dir: &Path) -> Result<Vec<(PathBuf, SpoolEntry)>> {
+    let mut result = vec![];
+
+    if !dir.exists() {
+        return Ok(result);
+    }
+
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().map(|e| e != "json").unwrap_or(true) {
+            continue;
+        }
+
+        let content = fs::read(&path).await?;
+        match serde_json::from_slice::<SpoolEntry>(&content) {
+            Ok(e) => result.push((path, e)),
+            Err(e) => println!("Warning: Broken spool file {:?}: {}", path, e),
+        }
+    }
+
+    result.sort_by_key(|(_, e)| e.created_at);
+
+    Ok(result)
+}
+
+pub async fn remove(path: &Path) -> Result<()> {
+    fs::remove_file(path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::{load, remove, write, SpoolEntry};
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_spool_round_trip() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("azmonitor-spool-{}", rand::random::<u32>()));
+
+        let newer = SpoolEntry {
+            url: "https://hook/b".to_string(),
+            payload: json!({"msg_type": "interactive"}),
+            created_at: Utc::now(),
+            attempts: 4,
+            last_error: "status 502".to_string(),
+        };
+        let older = SpoolEntry {
+            url: "https://hook/a".to_string(),
+            created_at: Utc::now() - Duration::minutes(5),
+            ..newer.clone()
+        };
+
+        write(&dir, &newer).await?;
+        let path = write(&dir, &older).await?;
+        std::fs::write(dir.join("broken.json"), "{")?;
+
+        let loaded = load(&dir).await?;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].1.url, "https://hook/a");
+        assert_eq!(loaded[1].1.payload, json!({"msg_type": "interactive"}));
+
+        remove(&path).await?;
+        assert_eq!(load(&dir).await?.len(), 1);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
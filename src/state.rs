@@ -0,0 +1,348 @@
+//! alert state kept between runs, one open alert per failing job
+
+use crate::bean::Task;
+use crate::link::exec_url;
+use crate::utli::write_atomic;
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs;
+
+/// Where an alert was sent, used to send the follow ups to the same place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notified {
+    pub url: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    /// latest failed execution
+    pub task: Task,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    #[serde(default)]
+    pub notified: Vec<Notified>,
+    #[serde(default)]
+    pub first_notified_at: Option<DateTime<Utc>>,
+    /// escalation tiers already notified
+    #[serde(default)]
+    pub escalated: Vec<usize>,
+    /// open_id of who acked, cleared by a new failed execution
+    #[serde(default)]
+    pub acked_by: Option<String>,
+    #[serde(default)]
+    pub acked_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub snoozed_by: Option<String>,
+    /// automatic reruns, oldest first
+    #[serde(default)]
+    pub reruns: Vec<Rerun>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rerun {
+    /// the failed execution that was rerun
+    pub from_exec_id: String,
+    pub exec_id: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Alert {
+    /// Acked or snoozed, no more notifications for now.
+    pub fn muted(&self, now: DateTime<Utc>) -> bool {
+        self.acked_by.is_some() || self.snoozed_until.is_some_and(|u| u > now)
+    }
+
+    /// Everyone notified so far, people as @ and channels by name.
+    pub fn notified_line(&self) -> String {
+        let mut seen: Vec<String> = vec![];
+        for n in &self.notified {
+            let who = match n.user_id.as_str() {
+                "" => continue,
+                "all" => "@所有人".to_string(),
+                id => format!("<at id={}></at>", id),
+            };
+            if !seen.contains(&who) {
+                seen.push(who);
+            }
+        }
+        seen.join(" ")
+    }
+
+    /// Lines shown under the task on the card.
+    pub fn notes(&self, azkaban_url: Option<&str>) -> Vec<String> {
+        let mut notes = vec![];
+        let exec = |id: &str| match azkaban_url {
+            Some(base) => format!("[执行 {}]({})", id, exec_url(base, id)),
+            None => format!("执行 {}", id),
+        };
+        for (i, r) in self.reruns.iter().enumerate() {
+            if r.exec_id == self.task.exec_id {
+                notes.push(format!("🔁 自动重跑 {} 仍然失败", exec(&r.exec_id)));
+            } else if r.from_exec_id == self.task.exec_id {
+                notes.push(format!(
+                    "🔁 已第 {} 次自动重跑: {}",
+                    i + 1,
+                    exec(&r.exec_id)
+                ));
+            }
+        }
+        if !self.escalated.is_empty() {
+            notes.push(format!("📣 已通知: {}", self.notified_line()));
+        }
+        if let Some(by) = &self.acked_by {
+            notes.push(format!("✅ 已由 <at id={}></at> 确认", by));
+        }
+        if let (Some(until), Some(by)) = (self.snoozed_until, &self.snoozed_by) {
+            notes.push(format!(
+                "💤 <at id={}></at> 暂停提醒至 {}",
+                by,
+                until.with_timezone(&Local).format("%m-%d %H:%M")
+            ));
+        }
+        notes
+    }
+}
+
+/// A later successful execution of an alerted job
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub exec_id: String,
+    pub end_time: DateTime<Utc>,
+    pub submit_user: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AlertState {
+    pub alerts: HashMap<String, Alert>,
+    /// card id -> alert keys on the card, to render it again after a button click
+    #[serde(default)]
+    pub cards: HashMap<String, Vec<String>>,
+    /// last weekly flaky job report
+    #[serde(default)]
+    pub flaky_report_at: Option<DateTime<Utc>>,
+    /// last weekly report of unresolved authors
+    #[serde(default)]
+    pub unresolved_report_at: Option<DateTime<Utc>>,
+    /// last weekly team failure load report
+    #[serde(default)]
+    pub team_report_at: Option<DateTime<Utc>>,
+}
+
+pub fn job_key(project: &str, flow: &str, job: &str) -> String {
+    format!("{}.{}.{}", project, flow, job)
+}
+
+pub fn alert_key(task: &Task) -> String {
+    job_key(&task.project_name, &task.flow_id, &task.job_id)
+}
+
+pub async fn load(path: &Path) -> Result<AlertState> {
+    if !path.exists() {
+        return Ok(AlertState::default());
+    }
+    let content = fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub async fn save(path: &Path, state: &AlertState) -> Result<()> {
+    write_atomic(path, serde_json::to_vec_pretty(state)?).await
+}
+
+impl AlertState {
+    /// Open or refresh the alert of a failing task.
+    pub fn observe(&mut self, task: &Task, now: DateTime<Utc>) {
+        let alert = self.alerts.entry(alert_key(task)).or_insert_with(|| Alert {
+            task: task.clone(),
+            first_failed_at: task.end_time,
+            last_seen_at: now,
+            notified: vec![],
+            first_notified_at: None,
+            escalated: vec![],
+            acked_by: None,
+            acked_at: None,
+            snoozed_until: None,
+            snoozed_by: None,
+            reruns: vec![],
+        });
+        if alert.task.exec_id != task.exec_id {
+            alert.acked_by = None;
+            alert.acked_at = None;
+        }
+        alert.task = task.clone();
+        alert.last_seen_at = now;
+    }
+
+    pub fn record_notified(&mut self, task: &Task, url: &str, user_id: &str) {
+        if let Some(alert) = self.alerts.get_mut(&alert_key(task)) {
+            alert.first_notified_at.get_or_insert_with(Utc::now);
+            let n = Notified {
+                url: url.to_string(),
+                user_id: user_id.to_string(),
+            };
+            if !alert.notified.contains(&n) {
+                alert.notified.push(n);
+            }
+        }
+    }
+
+    /// Keep acks and snoozes written by the callback server while this run was busy.
+    pub fn merge_actions(&mut self, on_disk: &AlertState) {
+        for (key, disk) in &on_disk.alerts {
+            let Some(alert) = self.alerts.get_mut(key) else {
+                continue;
+            };
+            if disk.task.exec_id == alert.task.exec_id && disk.acked_at > alert.acked_at {
+                alert.acked_by = disk.acked_by.clone();
+                alert.acked_at = disk.acked_at;
+            }
+            if disk.snoozed_until > alert.snoozed_until {
+                alert.snoozed_until = disk.snoozed_until;
+                alert.snoozed_by = disk.snoozed_by.clone();
+            }
+        }
+    }
+
+    /// Drop the alerts not seen failing for `max_age`, e.g. of jobs that were
+    /// removed or never ran again, returns how many.
+    pub fn expire(&mut self, max_age: Duration, now: DateTime<Utc>) -> usize {
+        let before = self.alerts.len();
+        self.alerts.retain(|_, a| {
+            (now - a.last_seen_at)
+                .to_std()
+                .map_or(true, |age| age < max_age)
+        });
+        // cards with nothing open left
+        let alerts = &self.alerts;
+        self.cards
+            .retain(|_, keys| keys.iter().any(|k| alerts.contains_key(k)));
+        before - self.alerts.len()
+    }
+
+    /// Close the alerts whose job succeeded after the failure.
+    pub fn resolve(&mut self, recoveries: &HashMap<String, Recovery>) -> Vec<(Alert, Recovery)> {
+        let keys: Vec<String> = self
+            .alerts
+            .iter()
+            .filter(|(k, a)| {
+                recoveries
+                    .get(*k)
+                    .is_some_and(|r| r.end_time > a.task.end_time)
+            })
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|k| {
+                let alert = self.alerts.remove(&k)?;
+                Some((alert, recoveries.get(&k)?.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::Task;
+    use crate::state::{alert_key, AlertState, Recovery};
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_observe_and_resolve() {
+        let mut state = AlertState::default();
+        let now = Utc::now();
+
+        let mut first = Task::demo("warehouse", "a", "ou_a");
+        first.end_time = now - Duration::hours(2);
+        state.observe(&first, now - Duration::hours(1));
+
+        let mut rerun = first.clone();
+        rerun.exec_id = "1002".to_string();
+        rerun.end_time = now - Duration::minutes(30);
+        state.observe(&rerun, now);
+        state.record_notified(&rerun, "https://hook", "ou_a");
+        state.record_notified(&rerun, "https://hook", "ou_a");
+        state.observe(&Task::demo("warehouse", "b", "ou_a"), now);
+
+        let alert = &state.alerts[&alert_key(&first)];
+        assert_eq!(alert.task.exec_id, "1002");
+        assert_eq!(alert.first_failed_at, first.end_time);
+        assert_eq!(alert.notified.len(), 1);
+
+        let mut recoveries = HashMap::new();
+        // succeeded before the latest failure, still open
+        recoveries.insert(
+            alert_key(&first),
+            Recovery {
+                exec_id: "1001".to_string(),
+                end_time: now - Duration::minutes(45),
+                submit_user: "azkaban".to_string(),
+            },
+        );
+        assert!(state.resolve(&recoveries).is_empty());
+
+        recoveries.get_mut(&alert_key(&first)).unwrap().end_time = now;
+        let resolved = state.resolve(&recoveries);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].0.task.job_id, "a");
+        assert_eq!(state.alerts.len(), 1);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut state = AlertState::default();
+        let now = Utc::now();
+        state.observe(
+            &Task::demo("warehouse", "a", "ou_a"),
+            now - Duration::days(8),
+        );
+        state.observe(
+            &Task::demo("warehouse", "b", "ou_a"),
+            now - Duration::days(6),
+        );
+
+        let week = Duration::days(7).to_std().unwrap();
+        assert_eq!(state.expire(week, now), 1);
+        assert_eq!(
+            state.alerts.keys().collect::<Vec<_>>(),
+            vec!["warehouse.daily.b"]
+        );
+    }
+
+    #[test]
+    fn test_ack_cleared_by_new_failure() {
+        let mut state = AlertState::default();
+        let now = Utc::now();
+        let task = Task::demo("warehouse", "a", "ou_a");
+        state.observe(&task, now);
+
+        let mut on_disk = AlertState::default();
+        on_disk.observe(&task, now);
+        let acked = on_disk.alerts.get_mut(&alert_key(&task)).unwrap();
+        acked.acked_by = Some("ou_b".to_string());
+        acked.acked_at = Some(now);
+        acked.snoozed_until = Some(now + Duration::hours(4));
+        acked.snoozed_by = Some("ou_b".to_string());
+
+        state.merge_actions(&on_disk);
+        let alert = &state.alerts[&alert_key(&task)];
+        assert!(alert.muted(now));
+        assert_eq!(alert.notes(None).len(), 2);
+
+        let mut again = task.clone();
+        again.exec_id = "1003".to_string();
+        state.observe(&again, now);
+        let alert = &state.alerts[&alert_key(&task)];
+        assert!(alert.acked_by.is_none());
+        // still snoozed
+        assert!(alert.muted(now));
+        assert!(!alert.muted(now + Duration::hours(5)));
+    }
+}
@@ -1,12 +1,11 @@
+//! Card elements for Feishu card JSON 2.0
+
 use serde_json::{json, Value};
 
 pub async fn div_at_user(user_id: &str) -> Value {
     json!({
-          "tag": "div",
-          "text": {
-              "content": format!(" <at id={}></at>", user_id),
-              "tag": "lark_md"
-          }
+          "tag": "markdown",
+          "content": format!("<at id={}></at> 以下任务执行异常需要处理:", user_id)
       }
     )
 }
@@ -14,11 +13,9 @@ pub async fn div_at_user(user_id: &str) -> Value {
 pub async fn div_project(project: &str) -> Value {
     json!(
            {
-                "tag": "div",
-                "text": {
-                  "content": format!("  in ***{}***", project),
-                  "tag": "lark_md"
-                }
+                "tag": "markdown",
+                "content": format!("**Project**: {}", project),
+                "text_size": "heading"
             }
     )
 }
@@ -26,11 +23,8 @@ pub async fn div_project(project: &str) -> Value {
 pub async fn div_flow_and_project(flow: &str, project: &str) -> Value {
     json!(
            {
-                "tag": "div",
-                "text": {
-                  "content": format!("    in **{}.{}**",project, flow),
-                  "tag": "lark_md"
-                }
+                "tag": "markdown",
+                "content": format!("**Flow**: {}.{}", project, flow)
             }
     )
 }
@@ -38,11 +32,8 @@ pub async fn div_flow_and_project(flow: &str, project: &str) -> Value {
 pub async fn div_message(message: &str) -> Value {
     json!(
                   {
-                  "tag": "div",
-                  "text": {
-                    "content": format!("{}", message),
-                    "tag": "lark_md"
-                  }
+                  "tag": "markdown",
+                  "content": message
                 }
 
     )
@@ -53,3 +44,119 @@ pub async fn hr() -> Value {
         "tag": "hr"
     })
 }
+
+/// One row of a flow table, every cell gets the same weight.
+pub async fn column_set(cells: &[String], bold: bool) -> Value {
+    let columns: Vec<Value> = cells
+        .iter()
+        .map(|c| {
+            let content = if bold {
+                format!("**{}**", c)
+            } else {
+                c.to_string()
+            };
+            json!({
+                "tag": "column",
+                "width": "weighted",
+                "weight": 1,
+                "vertical_align": "top",
+                "elements": [
+                    {
+                        "tag": "markdown",
+                        "content": content,
+                        "text_size": "normal_v2"
+                    }
+                ]
+            })
+        })
+        .collect();
+
+    json!({
+        "tag": "column_set",
+        "flex_mode": "none",
+        "horizontal_spacing": "default",
+        "columns": columns
+    })
+}
+
+pub async fn collapsible_panel(title: &str, content: &str) -> Value {
+    json!({
+        "tag": "collapsible_panel",
+        "expanded": false,
+        "header": {
+            "title": {
+                "tag": "markdown",
+                "content": title
+            },
+            "icon": {
+                "tag": "standard_icon",
+                "token": "down-small-ccm_outlined",
+                "size": "16px 16px"
+            },
+            "icon_position": "right",
+            "icon_expanded_angle": -180
+        },
+        "elements": [
+            {
+                "tag": "markdown",
+                "content": content
+            }
+        ]
+    })
+}
+
+pub async fn button(text: &str, url: &str) -> Value {
+    json!({
+        "tag": "button",
+        "text": {
+            "tag": "plain_text",
+            "content": text
+        },
+        "type": "default",
+        "size": "small",
+        "behaviors": [
+            {
+                "type": "open_url",
+                "default_url": url
+            }
+        ]
+    })
+}
+
+pub async fn button_row(buttons: Vec<Value>) -> Value {
+    let columns: Vec<Value> = buttons
+        .into_iter()
+        .map(|b| {
+            json!({
+                "tag": "column",
+                "width": "auto",
+                "elements": [b]
+            })
+        })
+        .collect();
+
+    json!({
+        "tag": "column_set",
+        "flex_mode": "flow",
+        "horizontal_spacing": "small",
+        "columns": columns
+    })
+}
+
+pub async fn callback_button(text: &str, kind: &str, value: Value) -> Value {
+    json!({
+        "tag": "button",
+        "text": {
+            "tag": "plain_text",
+            "content": text
+        },
+        "type": kind,
+        "size": "small",
+        "behaviors": [
+            {
+                "type": "callback",
+                "value": value
+            }
+        ]
+    })
+}
@@ -0,0 +1,234 @@
+//! teams on top of individual owners: what they own, who is in them, their failure load
+
+use crate::bean::{FeishuUrl, InitConfig, Task};
+use crate::directory::Directory;
+use crate::flapping::History;
+use crate::oncall;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// The team owning what the task runs, else the team of its owner.
+pub fn team_of(config: &InitConfig, directory: &Directory, task: &Task) -> Option<String> {
+    let mut names: Vec<&String> = config.teams.keys().collect();
+    names.sort();
+
+    if let Some(name) = names
+        .iter()
+        .find(|n| config.teams[**n].owns.iter().any(|m| m.matches(task)))
+    {
+        return Some(name.to_string());
+    }
+    if task.owner.is_empty() {
+        return None;
+    }
+
+    let is_owner = |m: &String| m == &task.owner || directory.get(m) == Some(&task.owner);
+    names
+        .iter()
+        .find(|n| config.teams[**n].members.iter().any(is_owner))
+        .map(|n| n.to_string())
+        .or_else(|| directory.team_of(&task.owner).map(String::from))
+}
+
+/// Team of every task, the team lead owns team-owned tasks nobody else does.
+pub fn assign(
+    config: &InitConfig,
+    directory: &Directory,
+    name_mapping: &HashMap<String, String>,
+    tasks: &mut [Task],
+    now: DateTime<Utc>,
+) {
+    if config.teams.is_empty() && directory.people.iter().all(|p| p.team.is_none()) {
+        return;
+    }
+
+    for t in tasks.iter_mut() {
+        t.team = team_of(config, directory, t);
+        let Some(lead) = t
+            .team
+            .as_ref()
+            .and_then(|team| config.teams.get(team))
+            .and_then(|team| team.lead.as_deref())
+        else {
+            continue;
+        };
+        if !t.owner.is_empty() {
+            continue;
+        }
+        // the unowned reason stays for the card
+        match oncall::resolve(config, name_mapping, lead, now) {
+            Ok(id) => t.owner = id,
+            Err(e) => println!("Warning: Can't resolve team lead {}: {}", lead, e),
+        }
+    }
+}
+
+pub fn channel<'a>(config: &'a InitConfig, team: &str) -> Option<&'a FeishuUrl> {
+    let name = config.teams.get(team)?.channel.as_ref()?;
+    let channel = config.channels.get(name);
+    if channel.is_none() {
+        println!(
+            "Warning: team {} refers to unknown channel '{}'",
+            team, name
+        );
+    }
+    channel
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Load {
+    pub runs: usize,
+    pub failures: usize,
+    /// job key, failures, runs of the jobs that failed, most failures first
+    pub jobs: Vec<(String, usize, usize)>,
+}
+
+impl Load {
+    pub fn rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.runs as f64
+        }
+    }
+}
+
+/// Runs and failures since `since` per team, jobs without a team are left out.
+pub fn load(
+    history: &History,
+    team_of_job: impl Fn(&str) -> Option<String>,
+    since: DateTime<Utc>,
+) -> BTreeMap<String, Load> {
+    let mut result: BTreeMap<String, Load> = BTreeMap::new();
+
+    for (key, job) in history {
+        let Some(team) = team_of_job(key) else {
+            continue;
+        };
+        let runs: Vec<_> = job.runs.iter().filter(|r| r.end_time > since).collect();
+        if runs.is_empty() {
+            continue;
+        }
+        let failures = runs.iter().filter(|r| !r.passed()).count();
+
+        let load = result.entry(team).or_default();
+        load.runs += runs.len();
+        load.failures += failures;
+        if failures > 0 {
+            load.jobs.push((key.clone(), failures, runs.len()));
+        }
+    }
+
+    for load in result.values_mut() {
+        load.jobs
+            .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::{InitConfig, Task};
+    use crate::directory::{Directory, Person};
+    use crate::flapping::{History, JobRuns, Run};
+    use crate::team::{assign, load};
+    use chrono::{Duration, Utc};
+
+    fn config() -> InitConfig {
+        serde_json::from_value(serde_json::json!({
+            "feishu_url": [],
+            "target_cron_dir": [],
+            "mapping_file": "",
+            "db_full_url": "",
+            "teams": {
+                "platform": {"owns": [{"project": "infra_*"}], "lead": "wangwu"},
+                "warehouse": {"members": ["zhangsan"]}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_assign() {
+        let person = |name: &str, id: &str, team: Option<&str>| Person {
+            name: name.to_string(),
+            feishu: Some(id.to_string()),
+            team: team.map(String::from),
+            ..Default::default()
+        };
+        let directory = Directory {
+            people: vec![
+                person("lisi", "ou_ls", Some("report")),
+                person("zhangsan", "ou_zs", None),
+                person("wangwu", "ou_ww", None),
+            ],
+        };
+        let mapping = directory.name_mapping();
+
+        let mut tasks = vec![
+            Task::demo("infra_hdfs", "a", ""),
+            Task::demo("infra_hdfs", "b", "ou_zs"),
+            Task::demo("dwd", "c", "ou_zs"),
+            Task::demo("ads", "d", "ou_ls"),
+            Task::demo("ads", "e", "ou_x"),
+        ];
+        assign(&config(), &directory, &mapping, &mut tasks, Utc::now());
+
+        let teams: Vec<Option<&str>> = tasks.iter().map(|t| t.team.as_deref()).collect();
+        assert_eq!(
+            teams,
+            vec![
+                Some("platform"),
+                Some("platform"),
+                Some("warehouse"),
+                Some("report"),
+                None
+            ]
+        );
+        // the lead takes what nobody owns
+        assert_eq!(tasks[0].owner, "ou_ww");
+        assert_eq!(tasks[1].owner, "ou_zs");
+    }
+
+    #[test]
+    fn test_load() {
+        let now = Utc::now();
+        let job = |name: &str, statuses: &[i32]| JobRuns {
+            project: "dwd".to_string(),
+            flow: "daily".to_string(),
+            job: name.to_string(),
+            runs: statuses
+                .iter()
+                .enumerate()
+                .map(|(i, s)| Run {
+                    exec_id: i.to_string(),
+                    attempt: 0,
+                    status: *s,
+                    end_time: now - Duration::days(i as i64),
+                })
+                .collect(),
+        };
+        let mut history = History::new();
+        history.insert("dwd.daily.a".to_string(), job("a", &[70, 50, 70, 50]));
+        history.insert("dwd.daily.b".to_string(), job("b", &[50, 50]));
+        history.insert("dwd.daily.c".to_string(), job("c", &[70, 70, 70]));
+        history.insert("dwd.daily.x".to_string(), job("x", &[70]));
+
+        let teams = load(
+            &history,
+            |key| (key != "dwd.daily.x").then(|| "warehouse".to_string()),
+            now - Duration::hours(60),
+        );
+        let warehouse = &teams["warehouse"];
+        // runs of the last 2.5 days only
+        assert_eq!((warehouse.runs, warehouse.failures), (8, 5));
+        assert_eq!(
+            warehouse.jobs,
+            vec![
+                ("dwd.daily.c".to_string(), 3, 3),
+                ("dwd.daily.a".to_string(), 2, 3)
+            ]
+        );
+        assert_eq!(teams.len(), 1);
+    }
+}
@@ -1,9 +1,11 @@
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use flate2::read::GzDecoder;
-use mysql::{Row, Value};
+use mysql::{from_value_opt, Row, Value};
 use regex::Regex;
 use serde_json::Value as JsonValue;
+use std::ffi::OsString;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 async fn decode(content: Vec<u8>) -> Option<Vec<u8>> {
@@ -73,19 +75,22 @@ pub async fn decode_field(row: &Row, column: &str) -> Option<String> {
     }
 }
 
+/// A time column as Azkaban stores it, in epoch millis. Formatting it in SQL
+/// would give the time in the session time zone of the database instead.
 pub async fn get_datetime(row: &Row, column_name: &str) -> Option<DateTime<Utc>> {
-    row.get(column_name).and_then(|v: Value| {
-        if v == Value::NULL {
-            return None;
-        }
-        let mut hit = v.as_sql(true);
+    row.get(column_name).and_then(datetime_of)
+}
 
-        let hit = hit.trim_matches('\'');
+fn datetime_of(v: Value) -> Option<DateTime<Utc>> {
+    let millis: i64 = from_value_opt(v).ok()?;
+    Utc.timestamp_millis_opt(millis).single()
+}
 
-        NaiveDateTime::parse_from_str(hit, "%Y-%m-%d %H:%M:%S%.f")
-            .ok()
-            .map(|ndt| Utc.from_utc_datetime(&ndt))
-    })
+/// Epoch millis as Azkaban gives them, the epoch itself for the -1 of what did not run.
+pub fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis.max(0))
+        .single()
+        .unwrap_or_default()
 }
 
 pub fn duration(later: DateTime<Utc>, earlier: DateTime<Utc>) -> Duration {
@@ -109,10 +114,11 @@ SELECT
     t.flow_id,
     t.job_id,
     t.attempt,
+    t.status,
     t.input_params,
     t.output_params,
-    FROM_UNIXTIME(t.start_time / 1000) AS start_time,
-    FROM_UNIXTIME(t.end_time / 1000) AS end_time
+    t.start_time,
+    t.end_time
 FROM (
     SELECT
         ej.exec_id,
@@ -120,6 +126,7 @@ FROM (
         ej.flow_id,
         ej.job_id,
         ej.attempt,
+        ej.status,
         ej.input_params,
         ej.output_params,
         ej.start_time,
@@ -144,6 +151,214 @@ ORDER BY t.name, t.flow_id, t.job_id;
     Some(sql)
 }
 
+/// Latest successful run of every job in the last 7 days, with who submitted it.
+pub async fn success_sql() -> Option<&'static str> {
+    let sql = r"
+SELECT
+    t.name,
+    t.flow_id,
+    t.job_id,
+    t.exec_id,
+    t.submit_user,
+    t.end_time
+FROM (
+    SELECT
+        p.name,
+        ej.flow_id,
+        ej.job_id,
+        ej.exec_id,
+        ef.submit_user,
+        ej.end_time,
+        ROW_NUMBER() OVER (
+            PARTITION BY p.name, ej.flow_id, ej.job_id
+            ORDER BY ej.end_time DESC
+        ) AS rn
+    FROM azkaban.execution_jobs ej
+    JOIN azkaban.projects p
+        ON ej.project_id = p.id
+    JOIN azkaban.execution_flows ef
+        ON ef.exec_id = ej.exec_id
+    WHERE
+        ej.end_time > (UNIX_TIMESTAMP(NOW(3)) * 1000) - 7 * 24 * 60 * 60 * 1000
+        AND ej.status = 50
+) t
+WHERE t.rn = 1;
+      ";
+
+    Some(sql)
+}
+
+/// Final attempt of every job run since `?` (epoch millis), oldest first.
+pub async fn history_sql() -> Option<&'static str> {
+    let sql = r"
+SELECT
+    t.name,
+    t.flow_id,
+    t.job_id,
+    t.exec_id,
+    t.attempt,
+    t.status,
+    t.end_time
+FROM (
+    SELECT
+        p.name,
+        ej.flow_id,
+        ej.job_id,
+        ej.exec_id,
+        ej.attempt,
+        ej.status,
+        ej.end_time,
+        ROW_NUMBER() OVER (
+            PARTITION BY ej.exec_id, ej.flow_id, ej.job_id
+            ORDER BY ej.attempt DESC
+        ) AS rn
+    FROM azkaban.execution_jobs ej
+    JOIN azkaban.projects p
+        ON ej.project_id = p.id
+    WHERE
+        ej.end_time > ?
+        AND ej.status IN (50, 60, 70, 80, 120)
+) t
+WHERE t.rn = 1
+ORDER BY t.end_time;
+      ";
+
+    Some(sql)
+}
+
+pub async fn log_sql() -> Option<&'static str> {
+    let sql = r"
+SELECT log, enc_type
+FROM azkaban.execution_logs
+WHERE exec_id = ? AND name = ? AND attempt = ?
+ORDER BY start_byte DESC
+LIMIT 1
+      ";
+
+    Some(sql)
+}
+
+/// Azkaban `Status` codes as stored in `execution_jobs.status`
+pub fn status_name(status: i32) -> &'static str {
+    match status {
+        10 => "READY",
+        20 => "PREPARING",
+        30 => "RUNNING",
+        40 => "PAUSED",
+        50 => "SUCCEEDED",
+        55 => "KILLING",
+        60 => "KILLED",
+        70 => "FAILED",
+        80 => "FAILED_FINISHING",
+        90 => "SKIPPED",
+        100 => "DISABLED",
+        110 => "QUEUED",
+        120 => "FAILED_SUCCEEDED",
+        125 => "CANCELLED",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Inverse of `status_name`, for the statuses the web api gives by name.
+pub fn status_code(name: &str) -> i32 {
+    [10, 20, 30, 40, 50, 55, 60, 70, 80, 90, 100, 110, 120, 125]
+        .into_iter()
+        .find(|c| status_name(*c) == name)
+        .unwrap_or_default()
+}
+
+/// Failure category used by routing rules: failed, killed, cancelled, retrying or other
+pub fn status_category(status: i32, attempt: u8) -> &'static str {
+    match status {
+        70 | 80 | 120 => "failed",
+        55 | 60 => "killed",
+        125 => "cancelled",
+        _ if attempt > 0 => "retrying",
+        _ => "other",
+    }
+}
+
+/// Shell style glob, `*` for any run of characters and `?` for one.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let re = regex::escape(pattern)
+        .replace("\\*", ".*")
+        .replace("\\?", ".");
+
+    Regex::new(&format!("^{}$", re))
+        .map(|r| r.is_match(s))
+        .unwrap_or(false)
+}
+
+/// Last `max_lines` lines of a job log, starting from the first error line if there is one.
+pub fn log_excerpt(log: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = log.lines().filter(|l| !l.trim().is_empty()).collect();
+
+    let error_at = lines.iter().position(|l| {
+        let l = l.to_lowercase();
+        l.contains("error") || l.contains("exception")
+    });
+
+    let start = match error_at {
+        Some(i) if lines.len() - i <= max_lines => i,
+        _ => lines.len().saturating_sub(max_lines),
+    };
+
+    lines[start..].join("\n")
+}
+
+/// humantime durations from the config, `1s`, `5m`, `24h`
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    humantime::parse_duration(s.trim()).map_err(|e| anyhow::anyhow!("Bad duration '{}': {}", s, e))
+}
+
+/// RFC 3339, or `2025-08-01 02:00` in local time
+pub fn parse_time(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .and_then(|t| chrono::Local.from_local_datetime(&t).earliest())
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or_else(|| anyhow::anyhow!("Bad time '{}'", s))
+}
+
+/// `<path>.<suffix>`, next to the file itself.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Exclusive advisory lock on `<path>.lock`, held until the file is dropped.
+/// The monitor, `serve` and the silence cli are separate processes rewriting
+/// the same state and silence files.
+pub async fn lock_file(path: &Path) -> anyhow::Result<std::fs::File> {
+    let lock_path = sibling(path, "lock");
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        file.lock()?;
+        Ok(file)
+    })
+    .await?
+}
+
+/// Write a temporary file and rename it over `path`, readers never see half
+/// of it.
+pub async fn write_atomic(path: &Path, contents: Vec<u8>) -> anyhow::Result<()> {
+    let tmp = sibling(path, &format!("{}.tmp", std::process::id()));
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
 pub fn format_duration_chinese(d: Duration) -> String {
     let total_secs = d.as_secs();
     match total_secs {
@@ -172,3 +387,79 @@ pub fn format_duration_chinese(d: Duration) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utli::{
+        datetime_of, glob_match, lock_file, log_excerpt, sibling, status_category, status_code,
+        status_name, write_atomic,
+    };
+    use chrono::{FixedOffset, TimeZone, Utc};
+    use mysql::Value;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("dwd_*", "dwd_bill"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("ods_?", "ods_a"));
+        assert!(!glob_match("ods_?", "ods_ab"));
+        assert!(!glob_match("dwd.*", "dwdx"));
+        assert!(glob_match("a+b(c)", "a+b(c)"));
+    }
+
+    #[test]
+    fn test_status_category() {
+        assert_eq!(status_category(70, 0), "failed");
+        assert_eq!(status_category(60, 1), "killed");
+        assert_eq!(status_category(20, 1), "retrying");
+        assert_eq!(status_category(20, 0), "other");
+    }
+
+    #[test]
+    fn test_status_name() {
+        assert_eq!(status_name(70), "FAILED");
+        assert_eq!(status_code("FAILED"), 70);
+        assert_eq!(status_code("NOPE"), 0);
+        assert_eq!(status_name(125), "CANCELLED");
+        assert_eq!(status_name(1), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_log_excerpt() {
+        let log = "start\nload data\nERROR table not found\nat foo\nexit 1\n";
+        assert_eq!(log_excerpt(log, 5), "ERROR table not found\nat foo\nexit 1");
+        assert_eq!(log_excerpt(log, 2), "at foo\nexit 1");
+        assert_eq!(log_excerpt("a\nb\nc", 2), "b\nc");
+    }
+
+    #[test]
+    fn test_datetime_of() {
+        // 08:00 in Shanghai, what FROM_UNIXTIME would print there
+        let beijing = FixedOffset::east_opt(8 * 3600).unwrap();
+        let at = beijing.with_ymd_and_hms(2025, 7, 28, 8, 0, 0).unwrap();
+        let utc = at.with_timezone(&Utc);
+        assert_eq!(utc.to_rfc3339(), "2025-07-28T00:00:00+00:00");
+
+        let millis = at.timestamp_millis();
+        assert_eq!(datetime_of(Value::Int(millis)), Some(utc));
+        let text = Value::Bytes(millis.to_string().into_bytes());
+        assert_eq!(datetime_of(text), Some(utc));
+        assert_eq!(datetime_of(Value::NULL), None);
+    }
+
+    #[tokio::test]
+    async fn test_lock_and_write() {
+        let path = std::env::temp_dir().join(format!("state-{}.json", rand::random::<u32>()));
+        let held = lock_file(&path).await.unwrap();
+        let other = std::fs::File::open(sibling(&path, "lock")).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(held);
+        other.try_lock().unwrap();
+
+        write_atomic(&path, b"{}".to_vec()).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(sibling(&path, "lock")).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+//! checks of a parsed config beyond what serde catches, each problem with the
+//! line of the config file it comes from
+
+use crate::bean::InitConfig;
+use crate::flapping::report_due;
+use crate::locate::Source;
+use crate::oncall;
+use crate::utli::{parse_duration, parse_time};
+use chrono::{Local, NaiveDate, NaiveTime};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// like `routes[2].channels[0]`
+    pub path: String,
+    /// the offending value, its line is only given when the file has it there
+    pub value: String,
+    pub message: String,
+}
+
+impl Problem {
+    /// `line 12: retry.base_delay: ...`, without a line when the value is not
+    /// in the file, e.g. set from the environment.
+    pub fn describe(&self, source: &Source) -> String {
+        match source.line_of(&self.path, &self.value) {
+            Some(n) => format!("line {}: {}: {}", n, self.path, self.message),
+            None => format!("{}: {}", self.path, self.message),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn push(&mut self, path: String, value: &str, message: String) {
+        self.0.push(Problem {
+            path,
+            value: value.to_string(),
+            message,
+        });
+    }
+
+    fn duration(&mut self, path: String, value: &str) {
+        if let Err(e) = parse_duration(value) {
+            self.push(path, value, e.to_string());
+        }
+    }
+
+    fn time(&mut self, path: String, value: &str) {
+        if let Err(e) = parse_time(value) {
+            self.push(path, value, e.to_string());
+        }
+    }
+
+    fn clock(&mut self, path: String, value: &str) {
+        if NaiveTime::parse_from_str(value.trim(), "%H:%M").is_err() {
+            self.push(
+                path,
+                value,
+                format!("Bad time '{}', expected like 08:30", value),
+            );
+        }
+    }
+
+    fn report_at(&mut self, path: String, value: &Option<String>) {
+        if let Some(at) = value {
+            if let Err(e) = report_due(at, None, &Local::now()) {
+                self.push(path, at, e.to_string());
+            }
+        }
+    }
+
+    fn channel(&mut self, config: &InitConfig, path: String, name: &str) {
+        if !config.channels.contains_key(name) {
+            self.push(path, name, format!("Unknown channel '{}'", name));
+        }
+    }
+
+    fn owner(&mut self, config: &InitConfig, path: String, owner: &str) {
+        if let Some(rotation) = owner.strip_prefix(oncall::PREFIX) {
+            if !config.rotations.contains_key(rotation) {
+                self.push(path, owner, format!("Unknown rotation '{}'", rotation));
+            }
+        }
+    }
+}
+
+/// Everything wrong with the config, empty when it is fine.
+pub fn validate(config: &InitConfig) -> Vec<Problem> {
+    let mut p = Problems::default();
+
+    let unpolled = |c: &InitConfig| c.db_full_url.trim().is_empty() && c.azkaban_api.is_none();
+    if config.instances.is_empty() && unpolled(config) {
+        p.push(
+            "db_full_url".to_string(),
+            "",
+            "Neither a database nor azkaban_api to poll".to_string(),
+        );
+    }
+
+    let retry = &config.retry;
+    p.duration("retry.base_delay".to_string(), &retry.base_delay);
+    p.duration("retry.max_delay".to_string(), &retry.max_delay);
+    p.duration("retry.spool_max_age".to_string(), &retry.spool_max_age);
+    p.duration("state.expire_after".to_string(), &config.state.expire_after);
+
+    let digest = &config.digest;
+    if digest.enabled && digest.window.is_none() && digest.at.is_empty() {
+        p.push(
+            "digest.enabled".to_string(),
+            "true",
+            "Neither digest.window nor digest.at is set, nothing would be sent".to_string(),
+        );
+    }
+    if let Some(window) = &config.digest.window {
+        p.duration("digest.window".to_string(), window);
+    }
+    for (i, at) in config.digest.at.iter().enumerate() {
+        p.clock(format!("digest.at[{}]", i), at);
+    }
+
+    for (i, w) in config.flapping.windows.iter().enumerate() {
+        p.duration(format!("flapping.windows[{}]", i), w);
+    }
+    if !(0.0..=1.0).contains(&config.flapping.threshold) {
+        let value = config.flapping.threshold.to_string();
+        p.push(
+            "flapping.threshold".to_string(),
+            &value,
+            "Must be between 0 and 1".to_string(),
+        );
+    }
+    p.report_at("flapping.report_at".to_string(), &config.flapping.report_at);
+    p.report_at(
+        "identities.report_at".to_string(),
+        &config.identities.report_at,
+    );
+    p.report_at(
+        "team_report.report_at".to_string(),
+        &config.team_report.report_at,
+    );
+    p.duration("team_report.window".to_string(), &config.team_report.window);
+
+    if let Some(contact) = &config.contact {
+        p.duration("contact.ttl".to_string(), &contact.ttl);
+    }
+    if let Some(callback) = &config.callback {
+        p.duration("callback.snooze".to_string(), &callback.snooze);
+        if callback.listen.parse::<SocketAddr>().is_err() {
+            p.push(
+                "callback.listen".to_string(),
+                &callback.listen,
+                format!(
+                    "Bad address '{}', expected like 0.0.0.0:8088",
+                    callback.listen
+                ),
+            );
+        }
+    }
+
+    for (i, route) in config.routes.iter().enumerate() {
+        for (j, c) in route.channels.iter().enumerate() {
+            p.channel(config, format!("routes[{}].channels[{}]", i, j), c);
+        }
+        if let Some(a) = &route.assignee {
+            p.owner(config, format!("routes[{}].assignee", i), a);
+        }
+    }
+    for (project, owner) in &config.fallback.project_owners {
+        p.owner(
+            config,
+            format!("fallback.project_owners.{}", project),
+            owner,
+        );
+    }
+
+    for (i, r) in config.reruns.iter().enumerate() {
+        p.duration(format!("reruns[{}].delay", i), &r.delay);
+    }
+    for (i, e) in config.escalations.iter().enumerate() {
+        for (j, tier) in e.tiers.iter().enumerate() {
+            p.duration(
+                format!("escalations[{}].tiers[{}].after", i, j),
+                &tier.after,
+            );
+            for (k, c) in tier.channels.iter().enumerate() {
+                p.channel(
+                    config,
+                    format!("escalations[{}].tiers[{}].channels[{}]", i, j, k),
+                    c,
+                );
+            }
+        }
+    }
+    for (i, s) in config.silences.iter().enumerate() {
+        if let Some(start) = &s.start {
+            p.time(format!("silences[{}].start", i), start);
+        }
+        p.time(format!("silences[{}].end", i), &s.end);
+    }
+
+    let mut names: Vec<&String> = config.rotations.keys().collect();
+    names.sort();
+    for name in names {
+        let r = &config.rotations[name];
+        let path = |field: &str| format!("rotations.{}.{}", name, field);
+        if r.members.is_empty() {
+            p.push(path("members"), "", "Rotation has no members".to_string());
+        }
+        if NaiveDate::parse_from_str(r.start.trim(), "%Y-%m-%d").is_err() {
+            p.push(
+                path("start"),
+                &r.start,
+                format!("Bad date '{}', expected like 2025-07-28", r.start),
+            );
+        }
+        p.clock(path("handoff"), &r.handoff);
+        p.duration(path("length"), &r.length);
+        if let Some(tz) = &r.timezone {
+            if tz.parse::<chrono_tz::Tz>().is_err() {
+                p.push(path("timezone"), tz, format!("Unknown timezone '{}'", tz));
+            }
+        }
+        for (i, o) in r.overrides.iter().enumerate() {
+            p.time(path(&format!("overrides[{}].start", i)), &o.start);
+            p.time(path(&format!("overrides[{}].end", i)), &o.end);
+        }
+    }
+
+    let mut names: Vec<&String> = config.teams.keys().collect();
+    names.sort();
+    for name in names {
+        let team = &config.teams[name];
+        if let Some(c) = &team.channel {
+            p.channel(config, format!("teams.{}.channel", name), c);
+        }
+        if let Some(lead) = &team.lead {
+            p.owner(config, format!("teams.{}.lead", name), lead);
+        }
+    }
+
+    let mut seen: HashMap<&String, usize> = HashMap::new();
+    for (i, instance) in config.instances.iter().enumerate() {
+        let path = format!("instances[{}].name", i);
+        if instance.name.trim().is_empty() {
+            p.push(path, "", "An instance without a name".to_string());
+        } else {
+            let n = seen.entry(&instance.name).or_default();
+            if *n > 0 {
+                let message = format!("Instance '{}' is listed twice", instance.name);
+                p.push(path, &instance.name, message);
+            }
+            *n += 1;
+        }
+        if unpolled(&config.for_instance(instance)) {
+            p.push(
+                format!("instances[{}].db_full_url", i),
+                "",
+                "Neither a database nor azkaban_api to poll".to_string(),
+            );
+        }
+        for (j, route) in instance.routes.iter().enumerate() {
+            for (k, c) in route.channels.iter().enumerate() {
+                p.channel(
+                    config,
+                    format!("instances[{}].routes[{}].channels[{}]", i, j, k),
+                    c,
+                );
+            }
+        }
+    }
+
+    p.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bean::InitConfig;
+    use crate::locate::Source;
+    use crate::validate::validate;
+
+    #[test]
+    fn test_validate() {
+        let content = r#"
+feishu_url = []
+target_cron_dir = []
+mapping_file = ""
+db_full_url = "mysql://az"
+
+[retry]
+base_delay = "fast"
+
+[[routes]]
+project = "dwd_*"
+channels = ["dwd"]
+assignee = "oncall:nobody"
+
+[rotations.platform]
+members = ["a"]
+start = "2025-07-28"
+timezone = "Mars/Olympus"
+
+[[instances]]
+name = "prod"
+
+[[instances]]
+name = "prod"
+
+[[instances]]
+name = "api"
+db_full_url = ""
+azkaban_url = "https://az"
+azkaban_api = { username = "az", password = "x" }
+
+[[instances]]
+name = "nothing"
+db_full_url = ""
+"#;
+        let config: InitConfig = toml::from_str(content).unwrap();
+        let problems: Vec<String> = validate(&config)
+            .iter()
+            .map(|p| p.describe(&Source::new(content, "toml")))
+            .collect();
+
+        assert_eq!(problems.len(), 6, "{:#?}", problems);
+        assert!(problems[0].starts_with("line 8: retry.base_delay: Bad duration 'fast'"));
+        assert_eq!(
+            problems[1],
+            "line 12: routes[0].channels[0]: Unknown channel 'dwd'"
+        );
+        assert_eq!(
+            problems[2],
+            "line 13: routes[0].assignee: Unknown rotation 'nobody'"
+        );
+        assert_eq!(
+            problems[3],
+            "line 18: rotations.platform.timezone: Unknown timezone 'Mars/Olympus'"
+        );
+        assert_eq!(
+            problems[4],
+            "line 24: instances[1].name: Instance 'prod' is listed twice"
+        );
+        assert_eq!(
+            problems[5],
+            "line 34: instances[3].db_full_url: Neither a database nor azkaban_api to poll"
+        );
+
+        let mut config = config;
+        config.retry.base_delay = "1s".to_string();
+        config.routes.clear();
+        config.rotations.clear();
+        config.instances.remove(1);
+        config.instances.pop();
+        assert!(validate(&config).is_empty());
+    }
+
+    #[test]
+    fn test_validate_digest() {
+        let content = r#"
+feishu_url = []
+target_cron_dir = []
+mapping_file = ""
+db_full_url = "mysql://az"
+
+[digest]
+enabled = true
+"#;
+        let mut config: InitConfig = toml::from_str(content).unwrap();
+        let problems: Vec<String> = validate(&config)
+            .iter()
+            .map(|p| p.describe(&Source::new(content, "toml")))
+            .collect();
+        assert_eq!(
+            problems,
+            vec!["line 8: digest.enabled: Neither digest.window nor digest.at is set, nothing would be sent"]
+        );
+
+        config.digest.at = vec!["08:30".to_string()];
+        assert!(validate(&config).is_empty());
+    }
+}